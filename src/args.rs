@@ -1,8 +1,28 @@
 use anyhow::{Result, Context};
 use clap::{ArgAction, Parser};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use crate::matcher::RuleSpec;
+use crate::modules::logwatcher::checkpoint::CheckpointStore;
+use crate::modules::logwatcher::encoding::Encoding;
+use crate::modules::logwatcher::dedup::{Dedup, DedupSpec};
+use crate::modules::logwatcher::multiline::{Multiline, MultilineSpec};
+use crate::notifiers::email::SmtpSettings;
+use crate::notifiers::sms::SmsSettings;
+use crate::notifiers::discord::DiscordWebhook;
+use crate::notifiers::exec::ExecSpec;
+use crate::notifiers::file::FileSinkSettings;
+use crate::notifiers::template::Template;
+use crate::notifiers::split_to;
+use crate::notifiers::batch::{Batch, BatchSpec};
+use crate::notifiers::gotify::GotifySettings;
+use crate::notifiers::matrix::MatrixSettings;
+use crate::notifiers::ntfy::NtfySettings;
+use crate::notifiers::slack::IncomingWebhook;
+use crate::notifiers::webhook::WebhookSpec;
+
 /// CLI arguments for single-job mode or --config YAML multi-job mode.
 #[derive(Parser, Debug)]
 #[command(name = "dende-rs (デンデ, Dende)")]
@@ -13,13 +33,41 @@ pub struct Args {
     #[arg(short = 'P', long = "path")]
     pub path: Option<PathBuf>,
 
-    /// Literal term to search for (single-job CLI mode)
+    /// Literal term to search for, repeatable (single-job CLI mode)
     #[arg(short = 'S', long = "search")]
-    pub search: Option<String>,
+    pub search: Vec<String>,
 
-    /// Regular expression (Rust regex, single-job CLI mode)
+    /// Regular expression (Rust regex), repeatable (single-job CLI mode)
     #[arg(short = 'R', long = "regex")]
-    pub regex: Option<String>,
+    pub regex: Vec<String>,
+
+    /// Drop matched lines containing this literal term, repeatable (single-job CLI mode)
+    #[arg(short = 'x', long = "exclude")]
+    pub exclude: Vec<String>,
+
+    /// Drop matched lines matching this regular expression, repeatable (single-job CLI mode)
+    #[arg(long = "exclude-regex")]
+    pub exclude_regex: Vec<String>,
+
+    /// Only match lines where every -S/-R pattern appears (single-job CLI mode)
+    #[arg(long = "require-all", default_value_t = false)]
+    pub require_all: bool,
+
+    /// Case-insensitive matching (single-job CLI mode)
+    #[arg(short = 'i', long = "ignore-case", default_value_t = false)]
+    pub ignore_case: bool,
+
+    /// Patterns only match whole words (single-job CLI mode)
+    #[arg(short = 'w', long = "whole-word", default_value_t = false)]
+    pub whole_word: bool,
+
+    /// Patterns only match at the start of the line (single-job CLI mode)
+    #[arg(long = "line-prefix", default_value_t = false)]
+    pub line_prefix: bool,
+
+    /// Patterns only match at the end of the line (single-job CLI mode)
+    #[arg(long = "line-suffix", default_value_t = false)]
+    pub line_suffix: bool,
 
     /// Recipients: plain text = console tag, 'tg:<CHAT_ID>' = Telegram (single-job CLI mode)
     #[arg(short = 'T', long = "to", value_delimiter = ',')]
@@ -33,6 +81,22 @@ pub struct Args {
     #[arg(long = "read-existing", default_value_t = true)]
     pub read_existing: bool,
 
+    /// Seconds an unterminated last line is held back waiting for its newline (single-job CLI mode)
+    #[arg(long = "partial-line-timeout", default_value_t = 5)]
+    pub partial_line_timeout: u64,
+
+    /// Lines of context shown before a match (single-job CLI mode)
+    #[arg(short = 'B', long = "context-before", default_value_t = 0)]
+    pub context_before: usize,
+
+    /// Lines of context shown after a match, the alert waits for them (single-job CLI mode)
+    #[arg(short = 'A', long = "context-after", default_value_t = 0)]
+    pub context_after: usize,
+
+    /// Encoding of the watched files: utf-8 (lossy), latin1, utf-16le or raw bytes (single-job CLI mode)
+    #[arg(long = "encoding", value_enum, default_value_t = Encoding::Utf8)]
+    pub encoding: Encoding,
+
     /// Telegram bot token (or ENV TELEGRAM_BOT_TOKEN) (single-job CLI mode)
     #[arg(long = "telegram-token", env = "TELEGRAM_BOT_TOKEN")]
     pub telegram_token: Option<String>,
@@ -45,6 +109,10 @@ pub struct Args {
     #[arg(long = "virustotal-token", env = "VIRUSTOTAL_TOKEN")]
     pub virustotal_token: Option<String>,
 
+    /// Directory where log-watcher jobs save their tail checkpoints to resume after a restart (single-job CLI mode)
+    #[arg(long = "state-dir", env = "DENDE_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
     /// YAML configuration file (multi-jobs)
     #[arg(short = 'C', long = "config")]
     pub config: Option<PathBuf>,
@@ -56,17 +124,76 @@ pub struct Args {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct JobSpec {
+    /// Stable job name, used to name its checkpoint file (defaults to "job-<index>")
+    #[serde(default)]
+    pub id: Option<String>,
     pub path: Option<PathBuf>,
     #[serde(default)]
     pub search: Option<String>,
     #[serde(default)]
     pub regex: Option<String>,
+    /// Named literal/regex rules, checked together with 'search' and 'regex'
+    #[serde(default)]
+    pub rules: Vec<RuleSpec>,
+    /// Literal terms that drop a matched line
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Regexes that drop a matched line
+    #[serde(default)]
+    pub exclude_regex: Vec<String>,
+    /// A line must match every rule, not just one
+    #[serde(default = "default_false")]
+    pub require_all: bool,
+    #[serde(default = "default_false")]
+    pub ignore_case: bool,
+    /// Patterns must not touch a word character on either side
+    #[serde(default = "default_false")]
+    pub whole_word: bool,
+    /// Patterns must start the line
+    #[serde(default = "default_false")]
+    pub line_prefix: bool,
+    /// Patterns must end the line
+    #[serde(default = "default_false")]
+    pub line_suffix: bool,
     #[serde(default)]
     pub to: Vec<String>,
     #[serde(default = "default_false")]
     pub recursive: bool,
     #[serde(default = "default_true")]
     pub read_existing: bool,
+    /// Seconds an unterminated last line is held back waiting for its newline
+    #[serde(default = "default_partial_line_timeout")]
+    pub partial_line_timeout: u64,
+    /// Encoding of the watched files: "utf-8" (lossy, default), "latin1", "utf-16le" or "raw"
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// Lines of context shown before a match
+    #[serde(default)]
+    pub context_before: usize,
+    /// Lines of context shown after a match (the alert waits for them)
+    #[serde(default)]
+    pub context_after: usize,
+    /// Seconds an alert waits for its "after" lines before being sent anyway
+    #[serde(default = "default_context_timeout")]
+    pub context_timeout: u64,
+    /// Group lines into multi-line records (stack traces) before matching
+    #[serde(default)]
+    pub multiline: Option<MultilineSpec>,
+    /// Count repeated alerts instead of sending them, then send one summary
+    #[serde(default)]
+    pub dedup: Option<DedupSpec>,
+    /// Message template of the alerts (overrides the global one)
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Per-destination templates, keyed by `to` entry ("tg:123") or scheme ("tg")
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+    /// Buffer the alerts and send them as digests
+    #[serde(default)]
+    pub batch: Option<BatchSpec>,
+    /// Per-destination digest settings, keyed like `templates`
+    #[serde(default)]
+    pub batches: BTreeMap<String, BatchSpec>,
     #[serde(default)]
     pub telegram_token: Option<String>,
     #[serde(default)]
@@ -75,21 +202,92 @@ pub struct JobSpec {
     pub virustotal_token: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ConfigFile {
+impl JobSpec {
+    /// All rules of the job: the 'search' and 'regex' shorthands followed by 'rules'.
+    pub fn rule_specs(&self) -> Vec<RuleSpec> {
+        self.search.iter().map(|s| RuleSpec::literal(s))
+            .chain(self.regex.iter().map(|r| RuleSpec::regex(r)))
+            .chain(self.rules.iter().cloned())
+            .collect()
+    }
+}
+
+/// Settings shared by all jobs (top-level YAML keys).
+#[derive(Debug, Deserialize, Clone)]
+pub struct GlobalSettings {
     #[serde(default)]
     pub telegram_token: Option<String>,
     #[serde(default)]
     pub virustotal_token: Option<String>,
+    /// SMTP server of the `email:` recipients
+    #[serde(default)]
+    pub smtp: Option<SmtpSettings>,
+    /// SMS gateway of the `sms:` recipients
+    #[serde(default)]
+    pub sms: Option<SmsSettings>,
+    /// Named webhooks of the `webhook:<name>` recipients
+    #[serde(default)]
+    pub webhooks: BTreeMap<String, WebhookSpec>,
+    /// Named incoming webhooks of the `slack:<name>` recipients
+    #[serde(default)]
+    pub slack: BTreeMap<String, IncomingWebhook>,
+    /// Named incoming webhooks of the `mattermost:<name>` recipients
+    #[serde(default)]
+    pub mattermost: BTreeMap<String, IncomingWebhook>,
+    /// Named webhooks of the `discord:<name>` recipients
+    #[serde(default)]
+    pub discord: BTreeMap<String, DiscordWebhook>,
+    /// Matrix account of the `matrix:<room_id>` recipients
+    #[serde(default)]
+    pub matrix: Option<MatrixSettings>,
+    /// ntfy server of the `ntfy:<topic>` recipients
+    #[serde(default)]
+    pub ntfy: Option<NtfySettings>,
+    /// Gotify server of the `gotify:<app>` recipients
+    #[serde(default)]
+    pub gotify: Option<GotifySettings>,
+    /// Rotation of the `file:<path>` recipients
+    #[serde(default)]
+    pub file: FileSinkSettings,
+    /// Named programs of the `exec:<name>` recipients
+    #[serde(default)]
+    pub exec: BTreeMap<String, ExecSpec>,
+    /// Default message template of the alerts of all jobs
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Directory for per-job checkpoints; unset = positions are kept in memory only
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+    /// Seconds between two checkpoint saves
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub globals: GlobalSettings,
     pub jobs: Vec<JobSpec>,
 }
 
 fn default_true() -> bool { true }
 fn default_false() -> bool { false }
+fn default_checkpoint_interval() -> u64 { 30 }
+fn default_partial_line_timeout() -> u64 { 5 }
+fn default_context_timeout() -> u64 { 5 }
+
+/// A `templates`/`batches` key must name a `to` entry or the scheme of one.
+fn check_destination_key(job: &JobSpec, key: &str) -> Result<()> {
+    let key = key.trim();
+    if job.to.iter().any(|to| to.trim() == key || split_to(to).0 == key) {
+        return Ok(());
+    }
+    anyhow::bail!("'{key}' matches no 'to' entry or scheme")
+}
 
 /// Load args from CLI or from YAML file
 pub fn load_jobs_from_cli_or_yaml(args: &Args)
- -> Result<(Vec<JobSpec>, Option<String>, Option<String>)> {
+ -> Result<(Vec<JobSpec>, GlobalSettings)> {
     if let Some(cfg_path) = args.config.as_ref() {
         let text = std::fs::read_to_string(cfg_path)
             .with_context(|| format!("Reading config file: {}", cfg_path.display()))?;
@@ -100,7 +298,13 @@ pub fn load_jobs_from_cli_or_yaml(args: &Args)
             anyhow::bail!("YAML file contains no jobs.");
         }
 
+        // Checkpoint file name -> job using it
+        let mut state_files: BTreeMap<String, usize> = BTreeMap::new();
         for (i, j) in cfg.jobs.iter().enumerate() {
+            let job_id = j.id.clone().unwrap_or_else(|| format!("job-{i}"));
+            if let Some(other) = state_files.insert(CheckpointStore::file_name(&job_id), i) {
+                anyhow::bail!("Job #{i}: id '{job_id}' clashes with job #{other} (same state file), use unique ids.");
+            }
             let is_vt = j
                 .hash
                 .as_ref()
@@ -120,6 +324,20 @@ pub fn load_jobs_from_cli_or_yaml(args: &Args)
             if j.to.is_empty() {
                 anyhow::bail!("Job #{i}: specify at least one recipient in 'to'.");
             }
+            if let Some(t) = j.template.as_ref() {
+                Template::parse(t).with_context(|| format!("Job #{i}: invalid template"))?;
+            }
+            for (to, t) in &j.templates {
+                check_destination_key(j, to).with_context(|| format!("Job #{i}: 'templates'"))?;
+                Template::parse(t).with_context(|| format!("Job #{i}: invalid template for '{to}'"))?;
+            }
+            if let Some(b) = j.batch.as_ref() {
+                Batch::new(b).with_context(|| format!("Job #{i}: invalid batch"))?;
+            }
+            for (to, b) in &j.batches {
+                check_destination_key(j, to).with_context(|| format!("Job #{i}: 'batches'"))?;
+                Batch::new(b).with_context(|| format!("Job #{i}: invalid batch for '{to}'"))?;
+            }
 
             if is_vt {
                 // VT job: no exigence search/regex/path
@@ -133,15 +351,58 @@ pub fn load_jobs_from_cli_or_yaml(args: &Args)
                         path.display()
                     );
                 }
-                if j.search.is_none() && j.regex.is_none() {
-                    anyhow::bail!("Job #{i}: specify 'search' or 'regex' for file/dir jobs.");
+                if j.search.is_none() && j.regex.is_none() && j.rules.is_empty() {
+                    anyhow::bail!("Job #{i}: specify 'search', 'regex' or 'rules' for file/dir jobs.");
+                }
+                crate::Matcher::from_job(j)
+                    .with_context(|| format!("Job #{i}: invalid rules"))?;
+                if let Some(ml) = j.multiline.as_ref() {
+                    Multiline::new(ml).with_context(|| format!("Job #{i}: invalid multiline"))?;
+                }
+                if let Some(dd) = j.dedup.as_ref() {
+                    Dedup::new(dd).with_context(|| format!("Job #{i}: invalid dedup"))?;
                 }
             }
         }
 
-        return Ok((cfg.jobs, cfg.telegram_token, cfg.virustotal_token));
+        if let Some(t) = cfg.globals.template.as_ref() {
+            Template::parse(t).context("Invalid global template")?;
+        }
+        // Templates of the sink settings, checked here so a typo is not a silently missing destination
+        if let Some(smtp) = cfg.globals.smtp.as_ref() {
+            Template::parse(&smtp.subject).context("Invalid smtp 'subject' template")?;
+        }
+        for (name, hook) in &cfg.globals.webhooks {
+            if let Some(body) = hook.body.as_ref() {
+                Template::parse(body).with_context(|| format!("Invalid 'body' template of webhook '{name}'"))?;
+            }
+        }
+        if cfg.globals.checkpoint_interval == 0 {
+            anyhow::bail!("'checkpoint_interval' must be at least 1 second.");
+        }
+
+        return Ok((cfg.jobs, cfg.globals));
     }
 
+    let globals = GlobalSettings {
+        telegram_token: None,
+        virustotal_token: None,
+        smtp: None,
+        sms: None,
+        webhooks: BTreeMap::new(),
+        slack: BTreeMap::new(),
+        mattermost: BTreeMap::new(),
+        discord: BTreeMap::new(),
+        matrix: None,
+        ntfy: None,
+        gotify: None,
+        file: FileSinkSettings::default(),
+        exec: BTreeMap::new(),
+        template: None,
+        state_dir: args.state_dir.clone(),
+        checkpoint_interval: default_checkpoint_interval(),
+    };
+
     // --- CLI (one job only) ---
     // --path + (--search|--regex), or --hash
     if let Some(h) = args.hash.as_ref() {
@@ -149,40 +410,114 @@ pub fn load_jobs_from_cli_or_yaml(args: &Args)
             anyhow::bail!("Specify at least one recipient via -T/--to.");
         }
         let job = JobSpec {
+            id: None,
             path: None,
             search: None,
             regex: None,
+            rules: Vec::new(),
+            exclude: Vec::new(),
+            exclude_regex: Vec::new(),
+            require_all: false,
+            ignore_case: false,
+            whole_word: false,
+            line_prefix: false,
+            line_suffix: false,
             to: args.to.clone(),
             recursive: false,
             read_existing: false,
+            partial_line_timeout: default_partial_line_timeout(),
+            encoding: Encoding::default(),
+            context_before: 0,
+            context_after: 0,
+            context_timeout: default_context_timeout(),
+            multiline: None,
+            dedup: None,
+            template: None,
+            templates: BTreeMap::new(),
+            batch: None,
+            batches: BTreeMap::new(),
             telegram_token: args.telegram_token.clone(),
             hash: Some(h.clone()),
             virustotal_token: args.virustotal_token.clone(),
         };
-        Ok((vec![job], None, None))
+        Ok((vec![job], globals))
     } else {
         let path = args.path.as_ref()
             .ok_or_else(|| anyhow::anyhow!("--path required in CLI mode (or use --config)"))?;
         if !path.is_dir() && !path.is_file() {
             anyhow::bail!("--path must be an existing file or directory");
         }
-        if args.search.is_none() && args.regex.is_none() {
+        if args.search.is_empty() && args.regex.is_empty() {
             anyhow::bail!("Specify --search or --regex (or use --config).");
         }
         if args.to.is_empty() {
             anyhow::bail!("Specify at least one recipient via -T/--to.");
         }
         let job = JobSpec {
+            id: None,
             path: Some(path.clone()),
-            search: args.search.clone(),
-            regex: args.regex.clone(),
+            search: None,
+            regex: None,
+            rules: args.search.iter().map(|s| RuleSpec::literal(s))
+                .chain(args.regex.iter().map(|r| RuleSpec::regex(r)))
+                .collect(),
+            exclude: args.exclude.clone(),
+            exclude_regex: args.exclude_regex.clone(),
+            require_all: args.require_all,
+            ignore_case: args.ignore_case,
+            whole_word: args.whole_word,
+            line_prefix: args.line_prefix,
+            line_suffix: args.line_suffix,
             to: args.to.clone(),
             recursive: args.recursive,
             read_existing: args.read_existing,
+            partial_line_timeout: args.partial_line_timeout,
+            encoding: args.encoding,
+            context_before: args.context_before,
+            context_after: args.context_after,
+            context_timeout: default_context_timeout(),
+            multiline: None,
+            dedup: None,
+            template: None,
+            templates: BTreeMap::new(),
+            batch: None,
+            batches: BTreeMap::new(),
             telegram_token: args.telegram_token.clone(),
             hash: None,
             virustotal_token: args.virustotal_token.clone(),
         };
-        Ok((vec![job], None, None))
+        Ok((vec![job], globals))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load `yaml` (jobs watching the temp dir) as the `-C` config.
+    fn load(name: &str, yaml: &str) -> Result<(Vec<JobSpec>, GlobalSettings)> {
+        let path = std::env::temp_dir().join(format!("dende-rs-args-{}-{name}.yaml", std::process::id()));
+        std::fs::write(&path, yaml.replace("TMP", &std::env::temp_dir().display().to_string())).unwrap();
+        let args = Args::parse_from(["dende-rs", "-C", path.to_str().unwrap()]);
+        let res = load_jobs_from_cli_or_yaml(&args);
+        std::fs::remove_file(&path).unwrap();
+        res
+    }
+
+    const JOB: &str = "jobs:\n  - path: TMP\n    search: ERR\n    to: ['slack:ops', 'log']\n";
+
+    #[test]
+    fn destination_keys_must_match_a_recipient() {
+        assert!(load("keys-ok", &format!("{JOB}    templates: {{ slack: 'x', 'slack:ops': 'y', console: 'z' }}\n")).is_ok());
+        let err = load("keys-bad", &format!("{JOB}    templates: {{ tg: 'x' }}\n")).unwrap_err();
+        assert!(format!("{err:#}").contains("'tg' matches no 'to' entry"), "{err:#}");
+        assert!(load("batches-bad", &format!("{JOB}    batches: {{ 'slack:dev': {{ max_events: 2 }} }}\n")).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sink_templates_are_checked() {
+        let smtp = "smtp: { host: h, from: 'a@b.c', subject: '{{ title' }\n";
+        assert!(format!("{:#}", load("smtp", &format!("{smtp}{JOB}")).unwrap_err()).contains("subject"));
+        let hook = "webhooks: { ops: { url: 'http://h', body: '{{#if x}}' } }\n";
+        assert!(format!("{:#}", load("hook", &format!("{hook}{JOB}")).unwrap_err()).contains("webhook 'ops'"));
+    }
+}
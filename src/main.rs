@@ -1,14 +1,21 @@
 use anyhow::Result;
 use clap::Parser;
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use std::time::Duration;
 
-use dende_rs::modules::logwatcher::events::spawn_job_watcher;
+use dende_rs::modules::logwatcher::checkpoint::CheckpointStore;
+use dende_rs::modules::logwatcher::events::{spawn_job_watcher, WatchOptions};
+use dende_rs::modules::logwatcher::dedup::Dedup;
+use dende_rs::modules::logwatcher::files::ReadOptions;
+use dende_rs::modules::logwatcher::multiline::Multiline;
 use dende_rs::modules::virustotal::spawn_virustotal_watcher;
 use env_logger::Builder;
-use log::{info,debug,error};
+use log::{info,debug,warn,error};
 
 use dende_rs::args::{Args, load_jobs_from_cli_or_yaml};
 use dende_rs::Matcher;
-use dende_rs::notifiers::Notifier;
+use dende_rs::notifiers::{Notifier, SinkContext};
+use dende_rs::notifiers::template::Template;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -26,57 +33,132 @@ async fn main() -> Result<()> {
         .init();
     debug!("Verbosity level: {:?}", level);
 
-    // Build the job list (from YAML or CLI) + optional global Telegram token
-    let (mut jobs, telegram_global_token, virustotal_global_token) = load_jobs_from_cli_or_yaml(&args)?;
+    // Build the job list (from YAML or CLI) + global settings (tokens, state directory)
+    let (mut jobs, globals) = load_jobs_from_cli_or_yaml(&args)?;
+    let telegram_global_token = globals.telegram_token.clone();
+    let virustotal_global_token = globals.virustotal_token.clone();
+    let sink_ctx = SinkContext::from(&globals);
+    let shutdown = Arc::new(AtomicBool::new(false));
 
     // Start each job in a blocking thread; the notifier runs in Tokio
-    let mut _thread_handles = Vec::new();
-    let mut _vt_tasks: Vec<tokio::task::JoinHandle<()>> = Vec::new();
+    let mut thread_handles = Vec::new();
+    let mut vt_tasks: Vec<tokio::task::JoinHandle<()>> = Vec::new();
+    let mut drains = Vec::new();
+    let several_jobs = jobs.len() > 1;
     for (idx, job) in jobs.drain(..).enumerate() {
+        let job_id = job.id.clone().unwrap_or_else(|| format!("job-{idx}"));
+        if several_jobs && job.id.is_none() && job.path.is_some() && globals.state_dir.is_some() {
+            warn!("Job #{idx} has no 'id': its state is saved as '{job_id}' and follows its position in the configuration");
+        }
+        let job_ctx = SinkContext {
+            job: Some(job_id.clone()),
+            telegram_token: job.telegram_token.clone().or_else(|| telegram_global_token.clone()),
+            template: job.template.as_ref().or(globals.template.as_ref()).map(|t| Template::parse(t)).transpose()?,
+            templates: job.templates.iter()
+                .map(|(to, t)| Ok((to.clone(), Template::parse(t)?)))
+                .collect::<Result<_>>()?,
+            batch: job.batch.clone(),
+            batches: job.batches.clone(),
+            ..sink_ctx.clone()
+        };
 
         // If job has "path" is search job "log-watcher"
-        if let Some(path) = job.path.as_ref() {
-            if path.is_dir() || path.is_file() {
-                let token = job.telegram_token.clone().or_else(|| telegram_global_token.clone());
-                let matcher = Matcher::from_spec(&job.search, &job.regex)?;
-                let notifier = Notifier::new(job.to.clone(), token)?;
-                let handle = spawn_job_watcher(
-                    idx,
-                    path.clone(),
-                    job.recursive,
-                    job.read_existing,
-                    matcher,
-                    notifier,
-                );
-                _thread_handles.push(handle);
-            }
+        if let Some(path) = job.path.as_ref()
+            && (path.is_dir() || path.is_file()) {
+            let matcher = Matcher::from_job(&job)?;
+            let notifier = Notifier::new(job.to.clone(), &job_ctx)?;
+            drains.push(notifier.drained());
+            let checkpoints = globals.state_dir.as_ref()
+                .map(|dir| CheckpointStore::new(dir, &job_id))
+                .transpose()?;
+            let opts = WatchOptions {
+                path: path.clone(),
+                recursive: job.recursive,
+                read_existing: job.read_existing,
+                checkpoints,
+                checkpoint_interval: Duration::from_secs(globals.checkpoint_interval),
+                read: ReadOptions {
+                    partial_timeout: Duration::from_secs(job.partial_line_timeout),
+                    encoding: job.encoding,
+                    context_before: job.context_before,
+                    context_after: job.context_after,
+                    context_timeout: Duration::from_secs(job.context_timeout),
+                    multiline: job.multiline.as_ref().map(Multiline::new).transpose()?,
+                    dedup: job.dedup.as_ref().map(Dedup::new).transpose()?.map(Arc::new),
+                },
+            };
+            let handle = spawn_job_watcher(
+                idx,
+                opts,
+                matcher,
+                notifier,
+                shutdown.clone(),
+            );
+            thread_handles.push(handle);
         }
 
         // If job has "hash" is virustotal checker job "virustotal-watcher"
-        if let Some(_) = job.hash.as_ref() {
-            if let Some(vt_token) = virustotal_global_token.to_owned() {
+        if job.hash.is_some()
+            && let Some(vt_token) = virustotal_global_token.to_owned() {
 
-                let telegram_token = job.telegram_token.clone().or_else(|| telegram_global_token.clone());
-                let notifier = Notifier::new(job.to.clone(), telegram_token)?;
+            let notifier = Notifier::new(job.to.clone(), &job_ctx)?;
+            drains.push(notifier.drained());
 
-                if let Some(hashes) = job.hash.clone() {
-                    let handle = tokio::spawn(async move {
-                        if let Err(e) = spawn_virustotal_watcher(
-                            &vt_token, 
-                            hashes,
-                            notifier
-                        ).await {
-                            error!("[virustotal] scheduler error: {e}");
-                        }
-                    });
-                    _vt_tasks.push(handle);
-                }
+            if let Some(hashes) = job.hash.clone() {
+                let handle = tokio::spawn(async move {
+                    if let Err(e) = spawn_virustotal_watcher(
+                        &vt_token, 
+                        hashes,
+                        notifier
+                    ).await {
+                        error!("[virustotal] scheduler error: {e}");
+                    }
+                });
+                vt_tasks.push(handle);
             }
         }
     }
 
     info!("dende-rs: ready. Press Ctrl+C to quit..");
-    let _ = tokio::signal::ctrl_c().await;
-    info!("Shutdown requested. Bye!");
+    shutdown_signal().await;
+    info!("Shutdown requested, saving job state..");
+
+    // Let the log-watcher threads save their checkpoints before leaving
+    shutdown.store(true, Ordering::Relaxed);
+    let _ = tokio::task::spawn_blocking(move || {
+        for handle in thread_handles {
+            let _ = handle.join();
+        }
+    }).await;
+    // Dropping the notifiers sends their buffered digests
+    for task in vt_tasks {
+        task.abort();
+    }
+    for drained in drains {
+        if tokio::time::timeout(Duration::from_secs(15), drained).await.is_err() {
+            warn!("Pending notifications not sent before shutdown");
+            break;
+        }
+    }
+    info!("Bye!");
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Wait for Ctrl+C, or SIGTERM (systemd, `docker stop`) on unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Cannot listen for SIGTERM: {e}"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use regex::{Regex, RegexSet};
+use anyhow::{Result, Context};
+use serde::Deserialize;
+
+use crate::args::JobSpec;
+
+/// One named pattern of a job: either a literal `search` or a `regex`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RuleSpec {
+    /// Name shown in alerts (defaults to the pattern itself)
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub search: Option<String>,
+    #[serde(default)]
+    pub regex: Option<String>,
+}
+
+impl RuleSpec {
+    pub fn literal(search: &str) -> Self {
+        Self { name: None, search: Some(search.to_string()), regex: None }
+    }
+
+    pub fn regex(regex: &str) -> Self {
+        Self { name: None, search: None, regex: Some(regex.to_string()) }
+    }
+}
+
+/// How the patterns of a job are matched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchOptions {
+    pub ignore_case: bool,
+    /// The pattern must not touch a word character on either side
+    pub whole_word: bool,
+    /// The pattern must start the line
+    pub line_prefix: bool,
+    /// The pattern must end the line
+    pub line_suffix: bool,
+}
+
+impl MatchOptions {
+    /// Wrap a regex so it honours the options.
+    fn wrap_regex(&self, re: &str) -> String {
+        let mut p = format!("(?:{re})");
+        if self.whole_word { p = format!(r"\b{p}\b"); }
+        if self.line_prefix { p = format!("^{p}"); }
+        if self.line_suffix { p = format!("{p}$"); }
+        if self.ignore_case { p = format!("(?i){p}"); }
+        p
+    }
+
+    /// Check the position constraints of a literal found at `line[start..end]`.
+    fn accepts(&self, line: &[u8], start: usize, end: usize) -> bool {
+        // Unicode word characters, like `\b` of the regex rules (invalid UTF-8 is not)
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        if self.line_prefix && start != 0 { return false; }
+        if self.line_suffix && end != line.len() { return false; }
+        if self.whole_word {
+            let (before, found, after) = (&line[..start], &line[start..end], &line[end..]);
+            if is_word(last_char(before)) && is_word(first_char(found)) { return false; }
+            if is_word(first_char(after)) && is_word(last_char(found)) { return false; }
+        }
+        true
+    }
+}
+
+/// First character of `bytes`, if they start with valid UTF-8.
+fn first_char(bytes: &[u8]) -> Option<char> {
+    bytes[..bytes.len().min(4)].utf8_chunks().next()?.valid().chars().next()
+}
+
+/// Last character of `bytes`, if they end with valid UTF-8.
+fn last_char(bytes: &[u8]) -> Option<char> {
+    let chunk = bytes[bytes.len().saturating_sub(4)..].utf8_chunks().last()?;
+    if !chunk.invalid().is_empty() {
+        return None;
+    }
+    chunk.valid().chars().last()
+}
+
+/// Rules that fired on a line, and the named groups their regexes captured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub rules: Vec<String>,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Literal rules, scanned in one pass by an Aho-Corasick automaton.
+#[derive(Clone, Debug)]
+struct Literals {
+    automaton: AhoCorasick,
+    /// Pattern index -> rule index
+    rules: Vec<usize>,
+}
+
+/// Regex rules, scanned in one pass by a `RegexSet` (text and bytes flavours).
+#[derive(Clone, Debug)]
+struct Regexes {
+    text: RegexSet,
+    bytes: regex::bytes::RegexSet,
+    /// Pattern index -> rule index
+    rules: Vec<usize>,
+    /// Pattern index -> standalone regexes, only for patterns with named groups
+    captures: Vec<Option<(Regex, regex::bytes::Regex)>>,
+}
+
+/// Line handed to the matcher, decoded or raw.
+#[derive(Clone, Copy)]
+enum Line<'a> {
+    Text(&'a str),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Line<'a> {
+    fn bytes(&self) -> &'a [u8] {
+        match self {
+            Line::Text(s) => s.as_bytes(),
+            Line::Bytes(b) => b,
+        }
+    }
+}
+
+/// A compiled list of rules, all checked in one pass.
+#[derive(Clone, Debug)]
+struct RuleSet {
+    names: Vec<String>,
+    literals: Option<Literals>,
+    regexes: Option<Regexes>,
+    opts: MatchOptions,
+}
+
+impl RuleSet {
+    fn new(rules: &[RuleSpec], opts: MatchOptions) -> Result<Self> {
+        let mut names = Vec::with_capacity(rules.len());
+        let (mut lit_patterns, mut lit_rules) = (Vec::new(), Vec::new());
+        let (mut re_patterns, mut re_rules) = (Vec::new(), Vec::new());
+        for (i, rule) in rules.iter().enumerate() {
+            let pattern = match (&rule.search, &rule.regex) {
+                // Aho-Corasick only folds ASCII case: other literals go through the regex set
+                (Some(s), None) if opts.ignore_case && !s.is_ascii() => {
+                    re_patterns.push(opts.wrap_regex(&regex::escape(s)));
+                    re_rules.push(i);
+                    s
+                }
+                (Some(s), None) => {
+                    lit_patterns.push(s.clone());
+                    lit_rules.push(i);
+                    s
+                }
+                (None, Some(r)) => {
+                    re_patterns.push(opts.wrap_regex(r));
+                    re_rules.push(i);
+                    r
+                }
+                _ => anyhow::bail!("Rule #{i}: specify exactly one of 'search' or 'regex'"),
+            };
+            names.push(rule.name.clone().unwrap_or_else(|| pattern.clone()));
+        }
+
+        let literals = if lit_patterns.is_empty() {
+            None
+        } else {
+            let automaton = AhoCorasickBuilder::new()
+                .ascii_case_insensitive(opts.ignore_case)
+                .build(&lit_patterns)
+                .context("Invalid search terms")?;
+            Some(Literals { automaton, rules: lit_rules })
+        };
+        let regexes = if re_patterns.is_empty() {
+            None
+        } else {
+            let text = RegexSet::new(&re_patterns).context("Invalid regex")?;
+            let captures = re_patterns
+                .iter()
+                .map(|p| -> Result<_> {
+                    let re = Regex::new(p).context("Invalid regex")?;
+                    if re.capture_names().flatten().next().is_none() {
+                        return Ok(None);
+                    }
+                    Ok(Some((re, regex::bytes::Regex::new(p).context("Invalid regex")?)))
+                })
+                .collect::<Result<_>>()?;
+            Some(Regexes {
+                text,
+                bytes: regex::bytes::RegexSet::new(&re_patterns).context("Invalid regex")?,
+                rules: re_rules,
+                captures,
+            })
+        };
+        Ok(Self { names, literals, regexes, opts })
+    }
+
+    /// Which rules fire on `line` (one flag per rule), plus the named groups captured.
+    fn hits(&self, line: Line<'_>, fields: Option<&mut BTreeMap<String, String>>) -> Vec<bool> {
+        let mut hits = vec![false; self.names.len()];
+        let bytes = line.bytes();
+        if let Some(lit) = &self.literals {
+            for m in lit.automaton.find_overlapping_iter(bytes) {
+                if self.opts.accepts(bytes, m.start(), m.end()) {
+                    hits[lit.rules[m.pattern().as_usize()]] = true;
+                }
+            }
+        }
+        let Some(re) = &self.regexes else { return hits };
+        let matched: Vec<usize> = match line {
+            Line::Text(s) => re.text.matches(s).into_iter().collect(),
+            Line::Bytes(b) => re.bytes.matches(b).into_iter().collect(),
+        };
+        for &i in &matched {
+            hits[re.rules[i]] = true;
+        }
+        if let Some(fields) = fields {
+            for (text_re, bytes_re) in matched.iter().filter_map(|&i| re.captures[i].as_ref()) {
+                capture_fields(line, text_re, bytes_re, fields);
+            }
+        }
+        hits
+    }
+}
+
+/// Matching engine: named literal and regex rules checked in one pass, then
+/// exclusions. With `require_all`, a line must fire every rule.
+#[derive(Clone, Debug)]
+pub struct Matcher {
+    rules: RuleSet,
+    excludes: Option<RuleSet>,
+    require_all: bool,
+}
+
+impl Matcher {
+    /// Build a matcher from a list of rules (each one has exactly one of search/regex).
+    pub fn new(rules: &[RuleSpec], opts: MatchOptions) -> Result<Self> {
+        if rules.is_empty() {
+            anyhow::bail!("Specify 'search', 'regex' or 'rules'");
+        }
+        Ok(Self { rules: RuleSet::new(rules, opts)?, excludes: None, require_all: false })
+    }
+
+    /// Build the matcher of a log-watcher job (rules, exclusions, flags).
+    /// The matching flags apply to both the rules and the exclusions.
+    pub fn from_job(job: &JobSpec) -> Result<Self> {
+        let opts = MatchOptions {
+            ignore_case: job.ignore_case,
+            whole_word: job.whole_word,
+            line_prefix: job.line_prefix,
+            line_suffix: job.line_suffix,
+        };
+        let mut matcher = Self::new(&job.rule_specs(), opts)?;
+        let excludes: Vec<RuleSpec> = job.exclude.iter().map(|s| RuleSpec::literal(s))
+            .chain(job.exclude_regex.iter().map(|r| RuleSpec::regex(r)))
+            .collect();
+        if !excludes.is_empty() {
+            matcher.excludes = Some(RuleSet::new(&excludes, opts).context("Invalid exclude")?);
+        }
+        matcher.require_all = job.require_all;
+        Ok(matcher)
+    }
+
+    /// Check a line against all rules, returning the ones that fired.
+    pub fn find(&self, line: &str) -> Option<Match> {
+        self.find_line(Line::Text(line))
+    }
+
+    /// Check a raw (undecoded) line against all rules.
+    pub fn find_bytes(&self, line: &[u8]) -> Option<Match> {
+        self.find_line(Line::Bytes(line))
+    }
+
+    /// Check if a line matches.
+    pub fn matches(&self, line: &str) -> bool {
+        self.find(line).is_some()
+    }
+
+    /// Check if a raw (undecoded) line matches.
+    pub fn matches_bytes(&self, line: &[u8]) -> bool {
+        self.find_bytes(line).is_some()
+    }
+
+    fn find_line(&self, line: Line<'_>) -> Option<Match> {
+        let mut fields = BTreeMap::new();
+        let hits = self.rules.hits(line, Some(&mut fields));
+        let fired = if self.require_all {
+            hits.iter().all(|h| *h)
+        } else {
+            hits.iter().any(|h| *h)
+        };
+        if !fired {
+            return None;
+        }
+        if let Some(ex) = &self.excludes
+            && ex.hits(line, None).iter().any(|h| *h) {
+            return None;
+        }
+        let rules = hits
+            .iter()
+            .zip(&self.rules.names)
+            .filter(|(hit, _)| **hit)
+            .map(|(_, name)| name.clone())
+            .collect();
+        Some(Match { rules, fields })
+    }
+}
+
+/// Add the named groups of `re` to `fields` (the first rule to capture a name wins).
+fn capture_fields(
+    line: Line<'_>,
+    text_re: &Regex,
+    bytes_re: &regex::bytes::Regex,
+    fields: &mut BTreeMap<String, String>,
+) {
+    match line {
+        Line::Text(s) => {
+            let Some(caps) = text_re.captures(s) else { return };
+            for name in text_re.capture_names().flatten() {
+                if let Some(m) = caps.name(name) {
+                    fields.entry(name.to_string()).or_insert_with(|| m.as_str().to_string());
+                }
+            }
+        }
+        Line::Bytes(b) => {
+            let Some(caps) = bytes_re.captures(b) else { return };
+            for name in bytes_re.capture_names().flatten() {
+                if let Some(m) = caps.name(name) {
+                    fields.entry(name.to_string()).or_insert_with(|| String::from_utf8_lossy(m.as_bytes()).into_owned());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(rules: &[RuleSpec], opts: MatchOptions) -> Matcher {
+        Matcher::new(rules, opts).unwrap()
+    }
+
+    #[test]
+    fn whole_word_sees_non_ascii_letters() {
+        let opts = MatchOptions { whole_word: true, ..Default::default() };
+        let lit = matcher(&[RuleSpec::literal("caf")], opts);
+        let re = matcher(&[RuleSpec::regex("caf")], opts);
+        for m in [&lit, &re] {
+            assert!(!m.matches("un café noir"));
+            assert!(!m.matches("écaf"));
+            assert!(m.matches("caf, fin"));
+        }
+        assert!(!lit.matches_bytes(b"caf\xc3\xa9"));
+        assert!(lit.matches_bytes(b"caf\xff"));
+    }
+
+    #[test]
+    fn rules_fire_together_and_capture_fields() {
+        let rules = [
+            RuleSpec { name: Some("fail".to_string()), ..RuleSpec::literal("FAILED") },
+            RuleSpec { name: Some("ip".to_string()), ..RuleSpec::regex(r"from (?P<ip>\d+\.\d+\.\d+\.\d+)") },
+            RuleSpec::literal("never"),
+        ];
+        let m = matcher(&rules, MatchOptions::default());
+        let hit = m.find("FAILED login from 10.0.0.7").unwrap();
+        assert_eq!(hit.rules, ["fail", "ip"]);
+        assert_eq!(hit.fields["ip"], "10.0.0.7");
+        assert_eq!(m.find("login from 10.0.0.7").unwrap().rules, ["ip"]);
+        assert_eq!(m.find_bytes(b"\xffFAILED").unwrap().rules, ["fail"]);
+        assert!(m.find("all good").is_none());
+    }
+
+    #[test]
+    fn whole_word_literals_and_regexes() {
+        let opts = MatchOptions { whole_word: true, ..Default::default() };
+        let m = matcher(&[RuleSpec::literal("err"), RuleSpec::regex("fail(ed)?")], opts);
+        assert!(m.matches("err: disk"));
+        assert!(m.matches("[err]"));
+        assert!(m.matches("login failed"));
+        assert!(!m.matches("error: disk"));
+        assert!(!m.matches("my_err"));
+        assert!(!m.matches("failure"));
+        // A pattern starting with a non-word character may touch a word
+        let m = matcher(&[RuleSpec::literal("=root")], opts);
+        assert!(m.matches("user=root"));
+        assert!(!m.matches("user=rooted"));
+    }
+
+    #[test]
+    fn ignore_case_folds_non_ascii_literals() {
+        let opts = MatchOptions { ignore_case: true, ..Default::default() };
+        let m = matcher(&[RuleSpec::literal("ÉCHEC"), RuleSpec::literal("Denied")], opts);
+        assert!(m.matches("connexion échec"));
+        assert!(m.matches("access DENIED"));
+        assert!(!matcher(&[RuleSpec::literal("ÉCHEC")], MatchOptions::default()).matches("échec"));
+        let m = matcher(&[RuleSpec::literal("straße")], opts);
+        assert!(m.matches("STRAẞE"));
+    }
+
+    #[test]
+    fn line_prefix_and_suffix() {
+        let opts = MatchOptions { line_prefix: true, line_suffix: true, ..Default::default() };
+        let m = matcher(&[RuleSpec::literal("ok"), RuleSpec::regex("a+")], opts);
+        assert!(m.matches("ok") && m.matches("aaa"));
+        assert!(!m.matches("ok!") && !m.matches(" aaa"));
+    }
+
+    #[test]
+    fn job_exclusions_and_require_all() {
+        let job: JobSpec = serde_yaml::from_str(
+            "rules: [{search: 'sshd'}, {regex: 'Failed password'}]\nexclude: ['10.0.0.1']\nrequire_all: true\nignore_case: true",
+        ).unwrap();
+        let m = Matcher::from_job(&job).unwrap();
+        assert!(m.matches("SSHD: failed password for root from 8.8.8.8"));
+        assert!(!m.matches("sshd: accepted password"));
+        assert!(!m.matches("sshd: Failed password for root from 10.0.0.1"));
+    }
+
+    #[test]
+    fn invalid_rules_are_refused() {
+        assert!(Matcher::new(&[], MatchOptions::default()).is_err());
+        assert!(Matcher::new(&[RuleSpec::regex("(")], MatchOptions::default()).is_err());
+        let both = RuleSpec { search: Some("a".to_string()), ..RuleSpec::regex("b") };
+        assert!(Matcher::new(&[both], MatchOptions::default()).is_err());
+    }
+}
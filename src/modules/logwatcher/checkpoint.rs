@@ -0,0 +1,212 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+use log::debug;
+
+use crate::modules::logwatcher::dedup::DedupEntry;
+use crate::utils::date::timestamp;
+
+/// Number of leading bytes hashed to fingerprint a file.
+const HEAD_BYTES: u64 = 1024;
+
+/// Key of a tailed file: (device, inode), or a path hash where inodes are unavailable.
+pub type FileKey = (u64, u64);
+
+/// Identity of a tailed file: device + inode plus a fingerprint of its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileIdentity {
+    pub dev: u64,
+    pub ino: u64,
+    pub head_len: u64,
+    pub head_hash: u64,
+}
+
+impl FileIdentity {
+    /// Compute the identity of an already opened file.
+    pub fn from_file(f: &File) -> io::Result<Self> {
+        let meta = f.metadata()?;
+        let (dev, ino) = dev_ino(&meta);
+        let head = read_head(f, HEAD_BYTES)?;
+        Ok(Self { dev, ino, head_len: head.len() as u64, head_hash: fnv1a(&head) })
+    }
+
+    /// Open `path` and compute its identity.
+    pub fn of(path: &Path) -> io::Result<Self> {
+        Self::from_file(&File::open(path)?)
+    }
+
+    /// Lookup key of the file, `path` is only used on platforms without inodes.
+    pub fn key(&self, path: &Path) -> FileKey {
+        if (self.dev, self.ino) == (0, 0) {
+            (0, fnv1a(path.to_string_lossy().as_bytes()))
+        } else {
+            (self.dev, self.ino)
+        }
+    }
+
+    /// True if `f` is the same file: same dev/inode and the same leading bytes.
+    pub fn matches(&self, f: &File) -> bool {
+        let Ok(meta) = f.metadata() else { return false };
+        if dev_ino(&meta) != (self.dev, self.ino) {
+            return false;
+        }
+        match read_head(f, self.head_len) {
+            Ok(head) => head.len() as u64 == self.head_len && fnv1a(&head) == self.head_hash,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Saved position of one file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileCheckpoint {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub line_no: u64,
+    pub identity: FileIdentity,
+}
+
+/// Everything a job persists between restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub job: String,
+    pub saved_at: String,
+    pub files: Vec<FileCheckpoint>,
+    /// Open deduplication windows, with their suppressed alert counts
+    #[serde(default)]
+    pub dedup: Vec<DedupEntry>,
+}
+
+impl Checkpoint {
+    /// Find the saved position of the file opened from `path`, first under that
+    /// name, then under any other name it had (e.g. rotated while stopped).
+    pub fn find(&self, path: &Path, f: &File) -> Option<&FileCheckpoint> {
+        self.files
+            .iter()
+            .find(|c| c.path == path && c.identity.matches(f))
+            .or_else(|| {
+                // An empty head fingerprints nothing, only trust it with inodes
+                self.files.iter().find(|c| {
+                    c.identity.head_len > 0 && (c.identity.dev, c.identity.ino) != (0, 0) && c.identity.matches(f)
+                })
+            })
+    }
+}
+
+/// JSON checkpoint file of one job inside the state directory.
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    job: String,
+    path: PathBuf,
+}
+
+impl CheckpointStore {
+    /// Create the state directory if needed and bind a store to `<dir>/<job>.json`.
+    pub fn new(dir: &Path, job: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Creating state directory: {}", dir.display()))?;
+        Ok(Self { job: job.to_string(), path: dir.join(Self::file_name(job)) })
+    }
+
+    /// Name of the checkpoint file of `job`; distinct jobs may map to the same one
+    /// (`a/b` and `a_b`), which the configuration loader rejects.
+    pub fn file_name(job: &str) -> String {
+        let name: String = job
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        format!("{name}.json")
+    }
+
+    /// Load the last saved checkpoint, `None` if the job never saved one.
+    pub fn load(&self) -> Result<Option<Checkpoint>> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Reading checkpoint: {}", self.path.display())),
+        };
+        let cp = serde_json::from_str(&text)
+            .with_context(|| format!("Parsing checkpoint: {}", self.path.display()))?;
+        Ok(Some(cp))
+    }
+
+    /// Atomically replace the checkpoint file (write to a temp file, then rename).
+    pub fn save(&self, files: Vec<FileCheckpoint>, dedup: Vec<DedupEntry>) -> Result<()> {
+        let cp = Checkpoint { job: self.job.clone(), saved_at: timestamp(), files, dedup };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&cp)?)
+            .with_context(|| format!("Writing checkpoint: {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Renaming checkpoint: {}", self.path.display()))?;
+        debug!("[{}] checkpoint saved ({} files)", self.job, cp.files.len());
+        Ok(())
+    }
+}
+
+/// Read up to `len` bytes from the start of the file, restoring its cursor afterwards.
+fn read_head(f: &File, len: u64) -> io::Result<Vec<u8>> {
+    let mut f = f;
+    let pos = f.stream_position()?;
+    f.seek(SeekFrom::Start(0))?;
+    let mut head = Vec::with_capacity(len as usize);
+    f.take(len).read_to_end(&mut head)?;
+    f.seek(SeekFrom::Start(pos))?;
+    Ok(head)
+}
+
+#[cfg(unix)]
+fn dev_ino(meta: &std::fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+#[cfg(not(unix))]
+fn dev_ino(_meta: &std::fs::Metadata) -> (u64, u64) {
+    // No stable inode on this platform: rely on the head fingerprint only.
+    (0, 0)
+}
+
+/// FNV-1a 64-bit: stable across builds, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_follows_the_file_under_a_new_name() {
+        let dir = std::env::temp_dir().join(format!("dende-rs-checkpoint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (log, rotated) = (dir.join("app.log"), dir.join("app.log.1"));
+        std::fs::write(&log, "first line\n").unwrap();
+        let saved = FileCheckpoint { path: log.clone(), offset: 11, line_no: 1, identity: FileIdentity::of(&log).unwrap() };
+        let cp = Checkpoint { files: vec![saved], ..Default::default() };
+
+        std::fs::rename(&log, &rotated).unwrap();
+        std::fs::write(&log, "other line\n").unwrap();
+        let found = cp.find(&rotated, &File::open(&rotated).unwrap()).unwrap();
+        assert_eq!((found.path.as_path(), found.offset), (log.as_path(), 11));
+        // Same name, another file
+        assert!(cp.find(&log, &File::open(&log).unwrap()).is_none());
+
+        let store = CheckpointStore::new(&dir, "a/b").unwrap();
+        assert!(store.load().unwrap().is_none());
+        store.save(cp.files.clone(), Vec::new()).unwrap();
+        let loaded = store.load().unwrap().unwrap();
+        assert_eq!(loaded.job, "a/b");
+        assert_eq!(loaded.files[0].identity, cp.files[0].identity);
+        assert!(dir.join("a_b.json").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
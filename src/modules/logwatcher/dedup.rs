@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::matcher::Match;
+
+/// Alert deduplication of a job (`dedup` YAML key).
+#[derive(Debug, Deserialize, Clone)]
+pub struct DedupSpec {
+    /// Seconds during which alerts with the same key are counted instead of sent
+    pub window: u64,
+    /// Fields the key is built from: "line" (digits and hex ids masked), "raw_line",
+    /// "path", "rules" or the name of a captured group
+    #[serde(default = "default_key")]
+    pub key: Vec<String>,
+}
+
+fn default_key() -> Vec<String> { vec!["line".to_string()] }
+
+/// A key inside its window, saved with the job checkpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupEntry {
+    pub key: String,
+    /// Unix time at which the window closes
+    pub until: i64,
+    /// Alerts counted instead of sent
+    pub suppressed: u64,
+    /// File, rules and line of the last suppressed alert
+    pub path: PathBuf,
+    pub rules: Vec<String>,
+    pub line: String,
+}
+
+/// Compiled `dedup` settings with the keys currently inside their window.
+#[derive(Debug)]
+pub struct Dedup {
+    pub window: u64,
+    key: Vec<String>,
+    entries: Mutex<HashMap<String, DedupEntry>>,
+}
+
+impl Dedup {
+    pub fn new(spec: &DedupSpec) -> Result<Self> {
+        if spec.window == 0 {
+            anyhow::bail!("dedup: 'window' must be at least 1 second");
+        }
+        if spec.key.is_empty() || spec.key.iter().any(|k| k.trim().is_empty()) {
+            anyhow::bail!("dedup: 'key' needs at least one field name");
+        }
+        Ok(Self { window: spec.window, key: spec.key.clone(), entries: Mutex::new(HashMap::new()) })
+    }
+
+    fn key(&self, path: &Path, line: &str, m: &Match) -> String {
+        self.key
+            .iter()
+            .map(|field| match field.as_str() {
+                "line" => normalize(line),
+                "raw_line" => line.to_string(),
+                "path" => path.display().to_string(),
+                "rules" => m.rules.join(","),
+                name => m.fields.get(name).cloned().unwrap_or_default(),
+            })
+            .collect::<Vec<_>>()
+            .join("\u{1f}")
+    }
+
+    /// True if the alert must be sent: first one of its key, or its window is over.
+    /// Otherwise it is counted for the "repeated" summary.
+    pub fn admit(&self, path: &Path, line: &str, m: &Match) -> bool {
+        let key = self.key(path, line, m);
+        let now = chrono::Utc::now().timestamp();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(e) = entries.get_mut(&key)
+            && now < e.until {
+            e.suppressed += 1;
+            e.path = path.to_path_buf();
+            e.rules = m.rules.clone();
+            e.line = line.to_string();
+            return false;
+        }
+        entries.insert(key.clone(), DedupEntry {
+            key,
+            until: now + self.window as i64,
+            suppressed: 0,
+            path: path.to_path_buf(),
+            rules: m.rules.clone(),
+            line: line.to_string(),
+        });
+        true
+    }
+
+    /// Forget the keys whose window is over, returning those with suppressed alerts.
+    pub fn expired(&self) -> Vec<DedupEntry> {
+        let now = chrono::Utc::now().timestamp();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let keys: Vec<String> = entries.values().filter(|e| e.until <= now).map(|e| e.key.clone()).collect();
+        keys.iter()
+            .filter_map(|k| entries.remove(k))
+            .filter(|e| e.suppressed > 0)
+            .collect()
+    }
+
+    /// Keys inside their window, ready to be saved.
+    pub fn snapshot(&self) -> Vec<DedupEntry> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+    }
+
+    /// Resume the windows saved by a previous run.
+    pub fn restore(&self, saved: &[DedupEntry]) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        for e in saved {
+            entries.insert(e.key.clone(), e.clone());
+        }
+    }
+}
+
+/// Line with what usually changes between repeats masked: numbers and hex ids
+/// (`0x7f3a`, `deadbeef01`) become `#`, digits inside words too (`pid4242` -> `pid#`).
+pub fn normalize(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut word = String::new();
+    for c in line.chars() {
+        if c.is_ascii_alphanumeric() {
+            word.push(c);
+        } else {
+            mask_word(&word, &mut out);
+            word.clear();
+            out.push(c);
+        }
+    }
+    mask_word(&word, &mut out);
+    out
+}
+
+fn mask_word(word: &str, out: &mut String) {
+    let hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+    let has_digit = word.chars().any(|c| c.is_ascii_digit());
+    let prefixed = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X"));
+    if has_digit && (hex(word) || prefixed.is_some_and(hex)) {
+        out.push('#');
+        return;
+    }
+    let mut in_digits = false;
+    for c in word.chars() {
+        if c.is_ascii_digit() {
+            if !in_digits {
+                out.push('#');
+            }
+            in_digits = true;
+        } else {
+            out.push(c);
+            in_digits = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn normalize_masks_numbers_and_hex_ids() {
+        assert_eq!(normalize("pid 4242 exited with 0x7F3a"), "pid # exited with #");
+        assert_eq!(normalize("object deadbeef01 freed"), "object # freed");
+        assert_eq!(normalize("worker pid4242 at 12:34:56.789"), "worker pid# at #:#:#.#");
+        assert_eq!(normalize("v2.10rc3"), "v#.#rc#");
+        // Words of hex letters only are words
+        assert_eq!(normalize("cafe babe added"), "cafe babe added");
+        assert_eq!(normalize("réseau 10.0.0.1 ok"), "réseau #.#.#.# ok");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn repeats_inside_the_window_are_counted() {
+        let dedup = Dedup::new(&DedupSpec { window: 60, key: default_key() }).unwrap();
+        let m = Match { rules: vec!["oom".to_string()], fields: BTreeMap::new() };
+        let path = Path::new("/var/log/app.log");
+        assert!(dedup.admit(path, "killed pid 1", &m));
+        assert!(!dedup.admit(path, "killed pid 2", &m));
+        assert!(!dedup.admit(path, "killed pid 3", &m));
+        assert!(dedup.admit(path, "restarted pid 3", &m));
+
+        let saved = dedup.snapshot();
+        let entry = saved.iter().find(|e| e.line == "killed pid 3").unwrap();
+        assert_eq!(entry.suppressed, 2);
+        // Nothing expired yet, and a restored window keeps counting
+        assert!(dedup.expired().is_empty());
+        let restored = Dedup::new(&DedupSpec { window: 60, key: default_key() }).unwrap();
+        restored.restore(&saved);
+        assert!(!restored.admit(path, "killed pid 9", &m));
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        assert!(Dedup::new(&DedupSpec { window: 0, key: default_key() }).is_err());
+        assert!(Dedup::new(&DedupSpec { window: 5, key: vec![] }).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    borrow::Cow,
+    io::{self, BufRead},
+};
+use serde::Deserialize;
+
+/// Text encoding of a watched file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum Encoding {
+    /// UTF-8, invalid bytes are replaced by U+FFFD
+    #[default]
+    #[serde(rename = "utf-8", alias = "utf8")]
+    #[value(name = "utf-8", alias = "utf8")]
+    Utf8,
+    /// ISO-8859-1, every byte is one character
+    #[serde(rename = "latin1", alias = "iso-8859-1")]
+    #[value(name = "latin1", alias = "iso-8859-1")]
+    Latin1,
+    /// UTF-16 little endian (Windows "Unicode" logs)
+    #[serde(rename = "utf-16le", alias = "utf16le")]
+    #[value(name = "utf-16le", alias = "utf16le")]
+    Utf16Le,
+    /// No decoding: lines are matched as bytes
+    #[serde(rename = "raw")]
+    #[value(name = "raw")]
+    Raw,
+}
+
+impl Encoding {
+    /// Read one line (terminator included) into `buf`.
+    /// Returns the number of bytes consumed and whether the line is terminated.
+    pub fn read_line<R: BufRead>(&self, reader: &mut R, buf: &mut Vec<u8>) -> io::Result<(usize, bool)> {
+        if *self != Encoding::Utf16Le {
+            let n = reader.read_until(b'\n', buf)?;
+            return Ok((n, buf.last() == Some(&b'\n')));
+        }
+
+        // UTF-16LE: the terminator is the code unit 0x000A, i.e. "\n\0" at an even offset
+        let start = buf.len();
+        // An unterminated line stops before a lone trailing byte, so the offset
+        // of the next read stays on a code unit boundary
+        let partial = |buf: &mut Vec<u8>| {
+            let n = (buf.len() - start) & !1;
+            buf.truncate(start + n);
+            Ok((n, false))
+        };
+        loop {
+            if reader.read_until(b'\n', buf)? == 0 {
+                return partial(buf);
+            }
+            if buf.last() != Some(&b'\n') {
+                return partial(buf);
+            }
+            if (buf.len() - start).is_multiple_of(2) {
+                // 0x0A is the high byte of some other code unit
+                continue;
+            }
+            let next = reader.fill_buf()?.first().copied();
+            match next {
+                None => return partial(buf),
+                Some(b) => {
+                    reader.consume(1);
+                    buf.push(b);
+                    if b == 0 {
+                        return Ok((buf.len() - start, true));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Strip the line terminator ("\n" or "\r\n" in this encoding).
+    pub fn trim_eol<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        let (lf, cr): (&[u8], &[u8]) = match self {
+            Encoding::Utf16Le => (b"\n\0", b"\r\0"),
+            _ => (b"\n", b"\r"),
+        };
+        let line = line.strip_suffix(lf).unwrap_or(line);
+        line.strip_suffix(cr).unwrap_or(line)
+    }
+
+    /// Decode a line for display and text matching. Never fails: undecodable
+    /// bytes become U+FFFD, and raw lines show non-printable bytes escaped.
+    pub fn decode<'a>(&self, line: &'a [u8]) -> Cow<'a, str> {
+        let text = match self {
+            Encoding::Utf8 => String::from_utf8_lossy(line),
+            Encoding::Latin1 => Cow::Owned(line.iter().map(|&b| b as char).collect()),
+            Encoding::Utf16Le => {
+                let units = line.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
+                Cow::Owned(
+                    char::decode_utf16(units)
+                        .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect(),
+                )
+            }
+            Encoding::Raw => Cow::Owned(line.escape_ascii().to_string()),
+        };
+        // Drop a byte order mark on the first line
+        match text {
+            Cow::Borrowed(s) => Cow::Borrowed(s.strip_prefix('\u{feff}').unwrap_or(s)),
+            Cow::Owned(s) if s.starts_with('\u{feff}') => Cow::Owned(s['\u{feff}'.len_utf8()..].to_string()),
+            owned => owned,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// Lines of `data` as read by `enc`, with their "terminated" flag.
+    fn lines(enc: Encoding, data: &[u8]) -> Vec<(Vec<u8>, bool)> {
+        let mut reader = io::BufReader::with_capacity(3, data);
+        let mut out = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            let (n, done) = enc.read_line(&mut reader, &mut buf).unwrap();
+            if n == 0 {
+                return out;
+            }
+            assert_eq!(n, buf.len());
+            out.push((buf, done));
+        }
+    }
+
+    #[test]
+    fn utf16_newline_byte_inside_a_character() {
+        // U+0A05 is "05 0A" and U+0A0A is "0A 0A": not line ends
+        let data = utf16("\u{0a05}x\u{0a0a}\ny\n");
+        let read = lines(Encoding::Utf16Le, &data);
+        assert_eq!(read.len(), 2);
+        assert!(read.iter().all(|(_, done)| *done));
+        let first = Encoding::Utf16Le.trim_eol(&read[0].0);
+        assert_eq!(Encoding::Utf16Le.decode(first), "\u{0a05}x\u{0a0a}");
+        assert_eq!(Encoding::Utf16Le.decode(Encoding::Utf16Le.trim_eol(&read[1].0)), "y");
+    }
+
+    #[test]
+    fn utf16_unterminated_and_crlf() {
+        let mut data = utf16("a\r\nb");
+        data.push(b'\n'); // half of a code unit at the end of the file
+        let read = lines(Encoding::Utf16Le, &data);
+        assert_eq!(read.len(), 2);
+        assert_eq!(Encoding::Utf16Le.decode(Encoding::Utf16Le.trim_eol(&read[0].0)), "a");
+        assert!(read[0].1);
+        assert!(!read[1].1);
+    }
+
+    #[test]
+    fn utf16_partial_line_stops_on_a_code_unit() {
+        // "ERR" then the first byte of "\n"
+        let mut data = utf16("ERR");
+        data.push(b'\n');
+        let mut reader = io::BufReader::new(&data[..]);
+        let mut buf = Vec::new();
+        assert_eq!(Encoding::Utf16Le.read_line(&mut reader, &mut buf).unwrap(), (6, false));
+        assert_eq!(buf, utf16("ERR"));
+
+        // Flushed at an odd length: the lone byte is left for the next read
+        let data = [b'x', 0, b'y'];
+        let mut buf = Vec::new();
+        assert_eq!(Encoding::Utf16Le.read_line(&mut &data[..], &mut buf).unwrap(), (2, false));
+        let mut rest = &data[2..];
+        buf.clear();
+        assert_eq!(Encoding::Utf16Le.read_line(&mut rest, &mut buf).unwrap(), (0, false));
+    }
+
+    #[test]
+    fn byte_encodings() {
+        let read = lines(Encoding::Latin1, b"caf\xe9\r\nend");
+        assert_eq!(read.len(), 2);
+        assert_eq!(Encoding::Latin1.decode(Encoding::Latin1.trim_eol(&read[0].0)), "café");
+        assert!(read[0].1 && !read[1].1);
+        assert_eq!(Encoding::Utf8.decode(b"ok\xff"), "ok\u{fffd}");
+        assert_eq!(Encoding::Raw.decode(b"a\x00\t"), "a\\x00\\t");
+    }
+
+    #[test]
+    fn byte_order_mark_is_dropped() {
+        assert_eq!(Encoding::Utf8.decode("\u{feff}first".as_bytes()), "first");
+        assert_eq!(Encoding::Utf16Le.decode(&utf16("\u{feff}first")), "first");
+    }
+}
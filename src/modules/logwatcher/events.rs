@@ -1,10 +1,12 @@
 use notify::{recommended_watcher, Event, EventKind, RecursiveMode, Watcher};
-use std::sync::mpsc;
+use std::path::PathBuf;
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc};
 use std::thread;
-use std::time::Duration;
-use log::error;
+use std::time::{Duration, Instant};
+use log::{info,error};
 
-use crate::modules::logwatcher::files::{initialize_files, read_new_lines, TailState};
+use crate::modules::logwatcher::checkpoint::CheckpointStore;
+use crate::modules::logwatcher::files::{close_file, drain_rotated, flush_partials, flush_pending, initialize_files, read_new_lines, ReadOptions, TailState};
 use crate::Matcher;
 use crate::notifiers::Notifier;
 
@@ -18,59 +20,91 @@ pub fn handle_event(
 ) {
     use notify::event::{CreateKind, ModifyKind, RenameMode};
 
-    let mut process_path = |path: std::path::PathBuf, from_start: bool| {
-        if let Some(name) = watch_file_name {
-            if path.file_name() != Some(name) { return; }
-        }
-        if let Err(e) = read_new_lines(&path, state, matcher, notifier, from_start) {
-            error!("FS read error {}: {}", path.display(), e);
-        }
+    let wanted = |path: &std::path::Path| match watch_file_name {
+        Some(name) => path.file_name() == Some(name),
+        None => true,
     };
 
     match event.kind {
-        EventKind::Create(CreateKind::File) => {
-            for path in event.paths { process_path(path, true); }
-        }
-        EventKind::Modify(ModifyKind::Data(_)) | EventKind::Modify(ModifyKind::Any) => {
-            for path in event.paths { process_path(path, false); }
-        }
-        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
-            for path in event.paths { process_path(path, true); }
+        // New data, new file or a file renamed into place: files are matched by identity,
+        // so a rotated file keeps its position and a replaced one is drained first.
+        EventKind::Create(CreateKind::File)
+        | EventKind::Modify(ModifyKind::Data(_))
+        | EventKind::Modify(ModifyKind::Any)
+        | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            for path in event.paths.into_iter().filter(|p| wanted(p)) {
+                if let Err(e) = read_new_lines(&path, state, matcher, notifier) {
+                    error!("FS read error {}: {}", path.display(), e);
+                }
+            }
         }
         EventKind::Remove(_) => {
-            for path in event.paths {
-                if let Some(name) = watch_file_name {
-                    if path.file_name() != Some(name) { continue; }
+            for path in event.paths.into_iter().filter(|p| wanted(p)) {
+                if let Err(e) = close_file(&path, state, matcher, notifier) {
+                    error!("FS read error {}: {}", path.display(), e);
                 }
-                state.offsets.remove(&path);
-                state.line_nums.remove(&path);
             }
         }
         _ => {}
     }
 }
 
+/// Per-job log-watcher settings.
+pub struct WatchOptions {
+    /// File or directory to watch.
+    pub path: PathBuf,
+    pub recursive: bool,
+    pub read_existing: bool,
+    /// Where to persist tail positions, if a state directory is configured.
+    pub checkpoints: Option<CheckpointStore>,
+    /// How often positions are saved (they are also saved on shutdown).
+    pub checkpoint_interval: Duration,
+    /// How lines are read from the watched files.
+    pub read: ReadOptions,
+}
+
 /// Spawn a watcher thread for a job. If a file path is provided, watch its parent
 /// directory and filter events to that file name; otherwise watch the directory.
+/// The thread exits (saving its checkpoint) once `shutdown` is set.
 pub fn spawn_job_watcher(
     idx: usize,
-    folder: std::path::PathBuf,
-    recursive: bool,
-    read_existing: bool,
+    opts: WatchOptions,
     matcher: crate::Matcher,
     notifier: Notifier,
+    shutdown: Arc<AtomicBool>,
 ) -> thread::JoinHandle<()> {
     thread::Builder::new()
         .name(format!("watcher-{}", idx))
         .spawn(move || {
-            let mut state = TailState::new();
+            let WatchOptions { path: folder, recursive, read_existing, checkpoints, checkpoint_interval, read } = opts;
+            // Events carry absolute paths, use the same form for the initial scan
+            let folder = folder.canonicalize().unwrap_or(folder);
+            let mut state = TailState::new(read);
 
-            // Initialize (reads existing content or sets offsets)
-            if let Err(e) = initialize_files(&folder, recursive, read_existing, &mut state, &matcher, &notifier) {
+            let checkpoint = match checkpoints.as_ref().map(|s| s.load()).transpose() {
+                Ok(cp) => cp.flatten(),
+                Err(e) => { error!("[job {}] checkpoint error: {}", idx, e); None }
+            };
+            if let Some(cp) = checkpoint.as_ref() {
+                info!("[job {}] resuming from checkpoint saved at {}", idx, cp.saved_at);
+                if let Some(dedup) = state.read.dedup.as_ref() {
+                    dedup.restore(&cp.dedup);
+                }
+            }
+            let save = |state: &TailState| {
+                if let Some(store) = checkpoints.as_ref()
+                    && let Err(e) = store.save(state.snapshot(), state.dedup_snapshot()) {
+                    error!("[job {}] checkpoint save error: {}", idx, e);
+                }
+            };
+
+            // Initialize (reads existing content, sets offsets or resumes from checkpoint)
+            if let Err(e) = initialize_files(&folder, recursive, read_existing, checkpoint.as_ref(), &mut state, &matcher, &notifier) {
                 error!("[job {}] init error: {}", idx, e);
                 return;
             }
-
+            flush_pending(&mut state, &matcher, &notifier, true);
+            save(&state);
             // Decide what to watch
             let watching_file = folder.is_file();
             let watch_root = if watching_file {
@@ -93,14 +127,24 @@ pub fn spawn_job_watcher(
                 return;
             }
 
-            loop {
+            let mut last_save = Instant::now();
+            while !shutdown.load(Ordering::Relaxed) {
                 match rx.recv_timeout(Duration::from_secs(1)) {
                     Ok(Ok(event)) => handle_event(event, &mut state, &matcher, &notifier, watch_name.as_deref()),
                     Ok(Err(err)) => error!("[job {}] event error: {}", idx, err),
                     Err(mpsc::RecvTimeoutError::Timeout) => {}
                     Err(e) => { error!("[job {}] channel error: {}", idx, e); break; }
                 }
+                flush_partials(&mut state, &matcher, &notifier);
+                drain_rotated(&mut state, &matcher, &notifier);
+                flush_pending(&mut state, &matcher, &notifier, false);
+                if last_save.elapsed() >= checkpoint_interval {
+                    save(&state);
+                    last_save = Instant::now();
+                }
             }
+            flush_pending(&mut state, &matcher, &notifier, true);
+            save(&state);
         })
         .expect("spawn watcher thread")
 }
\ No newline at end of file
@@ -1,45 +1,219 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fs::File,
-    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    io::{self, BufReader, Seek, SeekFrom},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use walkdir::WalkDir;
 use anyhow::Result;
 
 use crate::{utils::date::timestamp, Matcher};
-use crate::notifiers::Notifier;
-use log::{info,trace,error};
+use crate::matcher::Match;
+use crate::modules::logwatcher::checkpoint::{Checkpoint, FileCheckpoint, FileIdentity, FileKey};
+use crate::modules::logwatcher::dedup::{Dedup, DedupEntry};
+use crate::modules::logwatcher::encoding::Encoding;
+use crate::modules::logwatcher::multiline::{Multiline, Record};
+use crate::notifiers::{Detail, Format, Notifier, NotifyEvent, Severity};
+use log::{info,debug,trace,error};
 
-/// Per-file tailing state (byte offsets and line counters).
+/// How long a rotated file is still read after its last new line (the writer may
+/// not have reopened the log yet).
+const ROTATED_IDLE: Duration = Duration::from_secs(30);
+/// Files without new data for this long have their handle closed (reopened by
+/// identity when they change), so large directories do not exhaust descriptors.
+const HANDLE_IDLE: Duration = Duration::from_secs(300);
+
+/// One tailed file. Files are tracked by identity, `path` is only its latest known name.
+pub struct TailedFile {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub line_no: u64,
+    pub identity: FileIdentity,
+    /// Kept open so the file can still be drained after it is renamed or removed,
+    /// closed once the file is idle.
+    handle: Option<File>,
+    /// Last time new bytes were read from the file.
+    active: Instant,
+    /// Another file took its path: it is drained until idle, then forgotten.
+    rotated: bool,
+    /// When an unterminated last line was first seen (it is held back until then).
+    partial_since: Option<Instant>,
+    /// Multi-line record being assembled.
+    record: Option<Record>,
+    /// Last records read (line number, text), kept for the "before" context of the next match.
+    history: VecDeque<(u64, String)>,
+    /// Matches still collecting their "after" context.
+    pending: Vec<Hit>,
+}
+
+impl TailedFile {
+    /// The open handle, reopened from `path` if it was closed while idle.
+    fn file(&mut self) -> io::Result<&File> {
+        if self.handle.is_none() {
+            let f = File::open(&self.path)?;
+            if !self.identity.matches(&f) {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "file replaced since its handle was closed"));
+            }
+            self.handle = Some(f);
+        }
+        Ok(self.handle.as_ref().expect("handle just opened"))
+    }
+}
+
+/// A matched record with its surrounding records.
+struct Hit {
+    line_no: u64,
+    line: String,
+    m: Match,
+    before: Vec<(u64, String)>,
+    after: Vec<(u64, String)>,
+    since: Instant,
+}
+
+/// How lines are read from tailed files and turned into alerts.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// How long an unterminated last line is held back before being processed anyway.
+    pub partial_timeout: Duration,
+    /// Encoding of the watched files (`Raw` matches on bytes).
+    pub encoding: Encoding,
+    /// Lines shown before a matched line.
+    pub context_before: usize,
+    /// Lines shown after a matched line (the alert waits for them).
+    pub context_after: usize,
+    /// How long an alert waits for its "after" lines before being sent anyway.
+    pub context_timeout: Duration,
+    /// Group lines into multi-line records before matching.
+    pub multiline: Option<Multiline>,
+    /// Alerts repeated inside a window are counted instead of sent (state shared by clones).
+    pub dedup: Option<Arc<Dedup>>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            partial_timeout: Duration::from_secs(5),
+            encoding: Encoding::default(),
+            context_before: 0,
+            context_after: 0,
+            context_timeout: Duration::from_secs(5),
+            multiline: None,
+            dedup: None,
+        }
+    }
+}
+
+/// Per-job tailing state: tailed files keyed by identity, and the path -> file index.
+#[derive(Default)]
 pub struct TailState {
-    pub offsets: HashMap<PathBuf, u64>,
-    pub line_nums: HashMap<PathBuf, u64>,
+    pub files: HashMap<FileKey, TailedFile>,
+    pub paths: HashMap<PathBuf, FileKey>,
+    pub read: ReadOptions,
 }
 impl TailState {
-    pub fn new() -> Self {
-        Self { offsets: HashMap::new(), line_nums: HashMap::new() }
+    pub fn new(read: ReadOptions) -> Self {
+        Self { read, ..Self::default() }
+    }
+
+    /// Current position of every tracked file, ready to be saved.
+    pub fn snapshot(&self) -> Vec<FileCheckpoint> {
+        self.files
+            .values()
+            .map(|t| FileCheckpoint {
+                path: t.path.clone(),
+                offset: t.offset,
+                line_no: t.line_no,
+                identity: t.identity,
+            })
+            .collect()
+    }
+
+    /// Open deduplication windows, ready to be saved.
+    pub fn dedup_snapshot(&self) -> Vec<DedupEntry> {
+        self.read.dedup.as_ref().map(|d| d.snapshot()).unwrap_or_default()
+    }
+
+    /// Start tracking an opened file at the given position.
+    fn track(&mut self, path: &Path, handle: File, identity: FileIdentity, offset: u64, line_no: u64) {
+        let key = identity.key(path);
+        self.paths.insert(path.to_path_buf(), key);
+        self.files.insert(key, TailedFile {
+            path: path.to_path_buf(),
+            offset,
+            line_no,
+            identity,
+            handle: Some(handle),
+            active: Instant::now(),
+            rotated: false,
+            partial_since: None,
+            record: None,
+            history: VecDeque::new(),
+            pending: Vec::new(),
+        });
+    }
+
+    /// Stop tracking a file (its path entry is dropped only if it still points to it).
+    fn forget(&mut self, key: FileKey) {
+        if let Some(t) = self.files.remove(&key)
+            && self.paths.get(&t.path) == Some(&key) {
+            self.paths.remove(&t.path);
+        }
+    }
+
+    /// Start tailing `path` at EOF without reading its content.
+    fn start_at_eof(&mut self, path: &Path) -> io::Result<()> {
+        let f = File::open(path)?;
+        let len = f.metadata()?.len();
+        let id = FileIdentity::from_file(&f)?;
+        self.track(path, f, id, len, 0);
+        Ok(())
+    }
+
+    /// Restore a saved position if `path` is the file it was saved for (even under
+    /// another name). Returns false if the checkpoint has nothing valid for it.
+    fn resume(&mut self, path: &Path, checkpoint: &Checkpoint) -> io::Result<bool> {
+        let f = File::open(path)?;
+        let len = f.metadata()?.len();
+        let Some(saved) = checkpoint.find(path, &f) else { return Ok(false) };
+        if len < saved.offset {
+            return Ok(false);
+        }
+        debug!("Resuming {} at line {} (saved as {})", path.display(), saved.line_no, saved.path.display());
+        self.track(path, f, saved.identity, saved.offset, saved.line_no);
+        Ok(true)
     }
 }
 
 /// Discover initial files and either read them fully or start tailing from EOF.
+/// When a checkpoint is given, files are resumed from their saved position instead,
+/// and files unknown to the checkpoint (created while stopped) are read from the start.
 pub fn initialize_files(
     folder: &std::path::Path,
     recursive: bool,
     read_existing: bool,
+    checkpoint: Option<&Checkpoint>,
     state: &mut TailState,
     matcher: &Matcher,
     notifier: &Notifier,
 ) -> Result<()> {
+    let init_file = |path: &Path, state: &mut TailState| -> io::Result<()> {
+        match checkpoint {
+            Some(cp) => {
+                if !state.resume(path, cp)? {
+                    debug!("No valid checkpoint for {}, reading from start", path.display());
+                }
+                read_new_lines(path, state, matcher, notifier)
+            }
+            None if read_existing => read_new_lines(path, state, matcher, notifier),
+            None => state.start_at_eof(path),
+        }
+    };
+
     // Support both a single file and a directory
     if folder.is_file() {
-        if read_existing {
-            read_new_lines(folder, state, matcher, notifier, true)?;
-        } else {
-            let len = std::fs::metadata(folder).map(|m| m.len()).unwrap_or(0);
-            state.offsets.insert(folder.to_path_buf(), len);
-            state.line_nums.insert(folder.to_path_buf(), 0);
-        }
+        init_file(folder, state)?;
         return Ok(());
     }
 
@@ -50,27 +224,25 @@ pub fn initialize_files(
     };
 
     for entry in walker.filter_map(Result::ok).filter(|e| e.file_type().is_file()) {
-        let path = entry.into_path();
-        if read_existing {
-            read_new_lines(&path, state, matcher, notifier, true)?;
-        } else {
-            let len = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
-            state.offsets.insert(path.clone(), len);
-            state.line_nums.insert(path, 0);
-        }
+        init_file(entry.path(), state)?;
     }
     Ok(())
 }
 
-/// Read new lines from a file since last known offset; optionally from start.
+/// Read new lines from a file since its last known offset.
+///
+/// The file is looked up by identity (dev/inode + head fingerprint), not by path:
+/// - a known file under a new name (renamed by rotation) keeps its position;
+/// - a new file at a known path ("create" rotation) first drains the old file to EOF;
+/// - a known file whose head changed or that shrank ("copytruncate") restarts at 0;
+/// - an unknown file is read from the start.
 pub fn read_new_lines(
     path: &Path,
     state: &mut TailState,
     matcher: &Matcher,
     notifier: &Notifier,
-    from_scratch: bool,
 ) -> io::Result<()> {
-    let mut f = match File::open(path) {
+    let f = match File::open(path) {
         Ok(f) => f,
         Err(e) => {
             // File may be gone due to rotation.
@@ -80,52 +252,569 @@ pub fn read_new_lines(
             return Ok(());
         }
     };
+    let identity = FileIdentity::from_file(&f)?;
+    let key = identity.key(path);
+
+    // Another file used to live at this path: read what it has, and keep reading it
+    // until its writer moves on to the new file (see `drain_rotated`).
+    if let Some(&old) = state.paths.get(path)
+        && old != key {
+        info!("Rotation detected on {}, draining the previous file", path.display());
+        state.paths.remove(path);
+        if let Some(t) = state.files.get_mut(&old) {
+            t.rotated = true;
+            t.active = Instant::now();
+            drain(t, &state.read, false, matcher, notifier)?;
+        }
+    }
+
+    match state.files.get_mut(&key) {
+        Some(t) => {
+            let len = f.metadata()?.len();
+            if len < t.offset || !t.identity.matches(&f) {
+                info!("Truncation detected on {}, reading from start", path.display());
+                t.offset = 0;
+                t.line_no = 0;
+                t.partial_since = None;
+                t.record = None;
+                t.history.clear();
+            }
+            t.handle = Some(f);
+            t.rotated = false;
+            if t.path != path {
+                debug!("{} renamed to {}", t.path.display(), path.display());
+                let old_path = std::mem::replace(&mut t.path, path.to_path_buf());
+                if state.paths.get(&old_path) == Some(&key) {
+                    state.paths.remove(&old_path);
+                }
+            }
+            state.paths.insert(path.to_path_buf(), key);
+        }
+        None => state.track(path, f, identity, 0, 0),
+    }
+
+    let Some(t) = state.files.get_mut(&key) else { return Ok(()) };
+    drain(t, &state.read, false, matcher, notifier)?;
+    // The fingerprint covers more bytes as a small file grows
+    if let Some(f) = &t.handle {
+        t.identity = FileIdentity::from_file(f)?;
+    }
+    Ok(())
+}
+
+/// Keep reading the files replaced by a rotation, forgetting those idle for
+/// `ROTATED_IDLE`, and close the handles of files idle for `HANDLE_IDLE`.
+pub fn drain_rotated(state: &mut TailState, matcher: &Matcher, notifier: &Notifier) {
+    let mut done = Vec::new();
+    for (key, t) in state.files.iter_mut() {
+        if t.rotated {
+            if let Err(e) = drain(t, &state.read, false, matcher, notifier) {
+                error!("FS read error {}: {}", t.path.display(), e);
+            }
+            if t.active.elapsed() >= ROTATED_IDLE {
+                debug!("Rotated file {} is idle, closing it", t.path.display());
+                if let Err(e) = drain(t, &state.read, true, matcher, notifier) {
+                    error!("FS read error {}: {}", t.path.display(), e);
+                }
+                finish(t, &state.read, true, matcher, notifier);
+                done.push(*key);
+            }
+        } else if t.handle.is_some()
+            && t.active.elapsed() >= HANDLE_IDLE
+            && t.partial_since.is_none()
+            && t.record.is_none()
+            && t.pending.is_empty() {
+            trace!("Closing idle handle of {}", t.path.display());
+            t.handle = None;
+        }
+    }
+    for key in done {
+        state.forget(key);
+    }
+}
+
+/// Read what is left of a file that disappeared (removed or renamed away), then forget it.
+pub fn close_file(
+    path: &Path,
+    state: &mut TailState,
+    matcher: &Matcher,
+    notifier: &Notifier,
+) -> io::Result<()> {
+    let Some(&key) = state.paths.get(path) else { return Ok(()) };
+    if let Some(t) = state.files.get_mut(&key) {
+        drain(t, &state.read, true, matcher, notifier)?;
+        finish(t, &state.read, true, matcher, notifier);
+    }
+    state.forget(key);
+    Ok(())
+}
 
-    let mut last_pos = if from_scratch { 0 } else { *state.offsets.get(path).unwrap_or(&0) };
-    let meta_len = f.metadata().map(|m| m.len()).unwrap_or(0);
-    if meta_len < last_pos { last_pos = 0; } // rotation/truncation detected
+/// Close the multi-line records and send the alerts that waited long enough for
+/// more lines, or all of them with `force` (e.g. on shutdown). Also sends the
+/// summaries of the deduplication windows that are over.
+pub fn flush_pending(state: &mut TailState, matcher: &Matcher, notifier: &Notifier, force: bool) {
+    for t in state.files.values_mut() {
+        finish(t, &state.read, force, matcher, notifier);
+    }
+    if let Some(dedup) = &state.read.dedup {
+        repeated(dedup, notifier);
+    }
+}
 
-    f.seek(SeekFrom::Start(last_pos))?;
-    let reader = BufReader::new(f);
+/// Close the record in progress and send the pending alerts of a file, if overdue or forced.
+fn finish(t: &mut TailedFile, opts: &ReadOptions, force: bool, matcher: &Matcher, notifier: &Notifier) {
+    if let Some(ml) = &opts.multiline
+        && (force || ml.overdue(&t.record))
+        && let Some(rec) = t.record.take() {
+        on_record(t, opts, rec, matcher, notifier);
+    }
+    let path = &t.path;
+    t.pending.retain(|hit| {
+        let due = force || hit.since.elapsed() >= opts.context_timeout;
+        if due {
+            alert(path, hit, opts.dedup.as_deref(), notifier);
+        }
+        !due
+    });
+}
 
-    let mut byte_pos = last_pos;
-    let mut line_no = *state.line_nums.get(path).unwrap_or(&0);
+/// Process the held-back partial lines whose timeout has expired.
+pub fn flush_partials(state: &mut TailState, matcher: &Matcher, notifier: &Notifier) {
+    for t in state.files.values_mut() {
+        let overdue = t.partial_since.is_some_and(|since| since.elapsed() >= state.read.partial_timeout);
+        if overdue && let Err(e) = drain(t, &state.read, true, matcher, notifier) {
+            error!("FS read error {}: {}", t.path.display(), e);
+        }
+    }
+}
+
+/// Read a tracked file from its offset to EOF, alerting on matching lines.
+///
+/// The offset advances by the exact number of bytes consumed (CRLF included). An
+/// unterminated last line is left unread until its newline arrives, unless it has
+/// been waiting longer than the partial timeout or `flush_partial` is set.
+fn drain(
+    t: &mut TailedFile,
+    opts: &ReadOptions,
+    flush_partial: bool,
+    matcher: &Matcher,
+    notifier: &Notifier,
+) -> io::Result<()> {
+    let mut f = t.file()?.try_clone()?;
+    f.seek(SeekFrom::Start(t.offset))?;
+    let mut reader = BufReader::new(f);
+    let mut buf = Vec::new();
 
-    for line_res in reader.lines() {
-        let line = match line_res {
-            Ok(s) => s,
-            Err(e) => {
-                error!("Read line error {}: {e}", path.display());
+    loop {
+        buf.clear();
+        let (n, terminated) = opts.encoding.read_line(&mut reader, &mut buf)?;
+        if n == 0 {
+            t.partial_since = None;
+            break;
+        }
+        if !terminated {
+            let since = *t.partial_since.get_or_insert_with(Instant::now);
+            if !flush_partial && since.elapsed() < opts.partial_timeout {
+                trace!("Holding back partial line in {}", t.path.display());
                 break;
             }
+        }
+        t.partial_since = None;
+        t.offset += n as u64;
+        t.active = Instant::now();
+        t.line_no += 1;
+
+        // Decoding never fails, so a bad byte cannot stop the scan of the file
+        let raw = opts.encoding.trim_eol(&buf);
+        let line = opts.encoding.decode(raw).into_owned();
+        let raw = (opts.encoding == Encoding::Raw).then_some(raw);
+        let records = match &opts.multiline {
+            Some(ml) => ml.push(&mut t.record, t.line_no, line, raw),
+            None => vec![Record::new(t.line_no, line, raw)],
         };
-        line_no += 1;
-        byte_pos += (line.len() + 1) as u64; // +1 ~ "\n" (approx, works for UNIX logs)
-        if matcher.matches(&line) {
-
-            info!("File {:?} match for {:?}", &path, &matcher);
-
-            let _txt = format!(
-                "!dende-rs::log-watcher::matched!\n\nDate: {}\nFilename and line: {}:{}\nContent matched:\n\n{}",
-                timestamp(),
-                path.display(),
-                line_no,
-                line
-            );
-            let _html = format!(
-                "<b>!dende-rs::log-watcher::matched!</b>\n\n<i>Date:</i> <b>{}</b>\n<i>Filename and line:</i> <b>{}:{}</b>\n<i>Content matched:</i>\n\n{}",
-                timestamp(),
-                path.display(),
-                line_no,
-                line
-            );
-
-            trace!("\n{_txt}\n");
-            notifier.notify(&_txt);
-        }
-    }
-
-    state.offsets.insert(path.to_path_buf(), meta_len.max(byte_pos));
-    state.line_nums.insert(path.to_path_buf(), line_no);
+        for rec in records {
+            on_record(t, opts, rec, matcher, notifier);
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Match a complete record, feed it to the context buffers and alert (now, or once
+/// its "after" records are in).
+fn on_record(t: &mut TailedFile, opts: &ReadOptions, rec: Record, matcher: &Matcher, notifier: &Notifier) {
+    let line = rec.text();
+    let found = match &rec.raw {
+        Some(raw) => matcher.find_bytes(raw),
+        None => matcher.find(&line),
+    };
+
+    if !t.pending.is_empty() {
+        let path = &t.path;
+        t.pending.retain_mut(|hit| {
+            hit.after.push((rec.line_no, line.clone()));
+            let complete = hit.after.len() >= opts.context_after;
+            if complete {
+                alert(path, hit, opts.dedup.as_deref(), notifier);
+            }
+            !complete
+        });
+    }
+
+    if let Some(m) = found {
+        let hit = Hit {
+            line_no: rec.line_no,
+            line: line.clone(),
+            m,
+            before: t.history.iter().cloned().collect(),
+            after: Vec::new(),
+            since: Instant::now(),
+        };
+        if opts.context_after == 0 {
+            alert(&t.path, &hit, opts.dedup.as_deref(), notifier);
+        } else {
+            t.pending.push(hit);
+        }
+    }
+
+    if opts.context_before > 0 {
+        if t.history.len() == opts.context_before {
+            t.history.pop_front();
+        }
+        t.history.push_back((rec.line_no, line));
+    }
+}
+
+/// Send the alert for a matched line, unless `dedup` already sent the same one.
+fn alert(path: &Path, hit: &Hit, dedup: Option<&Dedup>, notifier: &Notifier) {
+    let Hit { line_no, line, m, before, after, .. } = hit;
+    if let Some(dedup) = dedup {
+        // A closed window must be reported before the key starts a new one
+        repeated(dedup, notifier);
+        if !dedup.admit(path, line, m) {
+            debug!("File {:?} match for {:?} suppressed (dedup)", &path, &m.rules);
+            return;
+        }
+    }
+    info!("File {:?} match for {:?}", &path, &m.rules);
+
+    let mut details = vec![
+        Detail::text("Date", timestamp()),
+        Detail::text("File", format!("{}:{}", path.display(), line_no)),
+        Detail::text("Rules", m.rules.join(", ")),
+    ];
+    details.extend(m.fields.iter().map(|(k, v)| Detail::text(k, v.clone())));
+    details.push(Detail::code("Content matched", line.clone()));
+    // Surrounding lines, the matched one marked with ">"
+    if !before.is_empty() || !after.is_empty() {
+        let lines: Vec<String> = before.iter().map(|(n, l)| format!("  {n}: {l}"))
+            .chain(std::iter::once(format!("> {line_no}: {line}")))
+            .chain(after.iter().map(|(n, l)| format!("  {n}: {l}")))
+            .collect();
+        details.push(Detail::code("Context", lines.join("\n")));
+    }
+
+    let numbered = |lines: &Vec<(u64, String)>| -> Vec<serde_json::Value> {
+        lines.iter().map(|(n, l)| serde_json::json!({ "line_no": n, "line": l })).collect()
+    };
+    let payload = serde_json::json!({
+        "file": path,
+        "line_no": line_no,
+        "line": line,
+        "rules": m.rules,
+        "captures": m.fields,
+        "context": { "before": numbered(before), "after": numbered(after) },
+    });
+
+    let ev = NotifyEvent {
+        fields: m.fields.clone(),
+        payload,
+        source: "log-watcher".to_string(),
+        severity: Severity::High,
+        title: "!dende-rs::log-watcher::matched!".to_string(),
+        details,
+        ..Default::default()
+    };
+    trace!("\n{}\n", ev.render(Format::Plain));
+    notifier.notify_event(ev);
+}
+
+/// Send a "repeated N more times" summary for every deduplication window that is over.
+fn repeated(dedup: &Dedup, notifier: &Notifier) {
+    for e in dedup.expired() {
+        info!("File {:?} match for {:?} repeated {} more times", &e.path, &e.rules, e.suppressed);
+        let details = vec![
+            Detail::text("Date", timestamp()),
+            Detail::text("File", e.path.display().to_string()),
+            Detail::text("Rules", e.rules.join(", ")),
+            Detail::text("Repeated", format!("{} more times in {}s", e.suppressed, dedup.window)),
+            Detail::code("Last occurrence", e.line.clone()),
+        ];
+        let payload = serde_json::json!({
+            "file": e.path,
+            "line": e.line,
+            "rules": e.rules,
+            "repeated": e.suppressed,
+            "window": dedup.window,
+        });
+        let ev = NotifyEvent {
+            payload,
+            source: "log-watcher".to_string(),
+            severity: Severity::Normal,
+            title: "!dende-rs::log-watcher::repeated!".to_string(),
+            details,
+            ..Default::default()
+        };
+        trace!("\n{}\n", ev.render(Format::Plain));
+        notifier.notify_event(ev);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::sync::Mutex;
+    use crate::matcher::{MatchOptions, RuleSpec};
+    use crate::modules::logwatcher::multiline::MultilineSpec;
+    use crate::notifiers::testing::capture;
+
+    /// A temp directory tailed by one job alerting on "ERR".
+    struct Watch {
+        dir: PathBuf,
+        state: TailState,
+        matcher: Matcher,
+        notifier: Notifier,
+        alerts: Arc<Mutex<Vec<NotifyEvent>>>,
+    }
+
+    impl Watch {
+        fn new(name: &str, read: ReadOptions) -> Self {
+            let dir = std::env::temp_dir().join(format!("dende-rs-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let matcher = Matcher::new(&[RuleSpec::literal("ERR")], MatchOptions::default()).unwrap();
+            let (notifier, alerts) = capture();
+            Self { dir, state: TailState::new(read), matcher, notifier, alerts }
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.join(name)
+        }
+
+        fn append(&self, name: &str, text: &str) {
+            let mut f = OpenOptions::new().create(true).append(true).open(self.path(name)).unwrap();
+            f.write_all(text.as_bytes()).unwrap();
+        }
+
+        fn read(&mut self, name: &str) {
+            read_new_lines(&self.path(name), &mut self.state, &self.matcher, &self.notifier).unwrap();
+        }
+
+        fn file(&mut self, name: &str) -> &mut TailedFile {
+            let key = FileIdentity::of(&self.path(name)).unwrap().key(&self.path(name));
+            self.state.files.get_mut(&key).unwrap()
+        }
+
+        /// Wait for the alerts sent so far, returning the payload of each one.
+        async fn alerts(self) -> Vec<serde_json::Value> {
+            let Self { dir, notifier, alerts, .. } = self;
+            let drained = notifier.drained();
+            drop(notifier);
+            drained.await;
+            fs::remove_dir_all(&dir).unwrap();
+            let alerts = alerts.lock().unwrap();
+            alerts.iter().map(|ev| ev.payload.clone()).collect()
+        }
+    }
+
+    fn lines(alerts: &[serde_json::Value]) -> Vec<&str> {
+        alerts.iter().map(|a| a["line"].as_str().unwrap()).collect()
+    }
+
+    /// Make the last read of a file look `age` old.
+    fn age(t: &mut TailedFile, age: Duration) {
+        t.active = Instant::now() - age;
+    }
+
+    #[tokio::test]
+    async fn create_rotation_drains_the_old_file_until_idle() {
+        let mut w = Watch::new("tail-create", ReadOptions::default());
+        w.append("app.log", "ERR 1\n");
+        w.read("app.log");
+        fs::rename(w.path("app.log"), w.path("app.log.1")).unwrap();
+        // The writer has not reopened the log yet
+        w.append("app.log.1", "ERR 2\n");
+        w.append("app.log", "ERR 3\n");
+        w.read("app.log");
+        assert_eq!(w.state.files.len(), 2);
+
+        w.append("app.log.1", "ERR 4\n");
+        drain_rotated(&mut w.state, &w.matcher, &w.notifier);
+        assert_eq!(w.state.files.len(), 2);
+        age(w.file("app.log.1"), ROTATED_IDLE);
+        w.append("app.log.1", "ERR 5");
+        drain_rotated(&mut w.state, &w.matcher, &w.notifier);
+        assert_eq!(w.state.files.len(), 1);
+        assert_eq!(w.state.paths.len(), 1);
+
+        let alerts = w.alerts().await;
+        assert_eq!(lines(&alerts), ["ERR 1", "ERR 2", "ERR 3", "ERR 4", "ERR 5"]);
+    }
+
+    #[tokio::test]
+    async fn copytruncate_restarts_from_the_start() {
+        let mut w = Watch::new("tail-truncate", ReadOptions::default());
+        w.append("app.log", "ERR a\nERR b\n");
+        w.read("app.log");
+        // Shorter than the offset
+        fs::write(w.path("app.log"), "ERR c\n").unwrap();
+        w.read("app.log");
+        assert_eq!(w.file("app.log").offset, 6);
+        // Longer than the offset, but the head changed
+        fs::write(w.path("app.log"), "ERR x\nERR y\n").unwrap();
+        w.read("app.log");
+        assert_eq!(w.file("app.log").line_no, 2);
+
+        let alerts = w.alerts().await;
+        assert_eq!(lines(&alerts), ["ERR a", "ERR b", "ERR c", "ERR x", "ERR y"]);
+        assert_eq!(alerts[4]["line_no"], 2);
+    }
+
+    #[tokio::test]
+    async fn renamed_file_keeps_its_position() {
+        let mut w = Watch::new("tail-rename", ReadOptions::default());
+        w.append("app.log", "ERR 1\n");
+        w.read("app.log");
+        fs::rename(w.path("app.log"), w.path("app.log.1")).unwrap();
+        w.append("app.log.1", "ERR 2\n");
+        w.read("app.log.1");
+        assert_eq!(w.state.files.len(), 1);
+        let renamed = w.path("app.log.1");
+        assert_eq!(w.file("app.log.1").path, renamed);
+        assert!(!w.state.paths.contains_key(&w.path("app.log")));
+
+        // Removed: what is left is read, then the file is forgotten
+        w.append("app.log.1", "ERR 3");
+        close_file(&w.path("app.log.1"), &mut w.state, &w.matcher, &w.notifier).unwrap();
+        assert!(w.state.files.is_empty() && w.state.paths.is_empty());
+
+        let alerts = w.alerts().await;
+        assert_eq!(lines(&alerts), ["ERR 1", "ERR 2", "ERR 3"]);
+    }
+
+    #[tokio::test]
+    async fn checkpoint_resumes_renamed_and_reads_new_files() {
+        let mut w = Watch::new("tail-resume", ReadOptions::default());
+        w.append("app.log", "ERR 1\nok\n");
+        w.read("app.log");
+        let checkpoint = Checkpoint { files: w.state.snapshot(), ..Default::default() };
+
+        // While stopped: more lines, then a rotation
+        w.append("app.log", "ERR 2\n");
+        fs::rename(w.path("app.log"), w.path("app.log.1")).unwrap();
+        w.append("app.log", "ERR 3\n");
+        w.state = TailState::new(ReadOptions::default());
+        initialize_files(&w.dir.clone(), false, false, Some(&checkpoint), &mut w.state, &w.matcher, &w.notifier).unwrap();
+        assert_eq!(w.file("app.log.1").line_no, 3);
+        assert_eq!(w.file("app.log").line_no, 1);
+
+        let mut alerts = w.alerts().await;
+        alerts.sort_by_key(|a| a["line"].to_string());
+        assert_eq!(lines(&alerts), ["ERR 1", "ERR 2", "ERR 3"]);
+        assert_eq!(alerts[1]["line_no"], 3);
+    }
+
+    #[tokio::test]
+    async fn crlf_offsets_and_partial_lines() {
+        let mut w = Watch::new("tail-partial", ReadOptions::default());
+        w.append("app.log", "ERR 1\r\nok\r\nERR 2");
+        w.read("app.log");
+        assert_eq!((w.file("app.log").offset, w.file("app.log").line_no), (11, 2));
+        assert!(w.file("app.log").partial_since.is_some());
+
+        // The rest of the held-back line arrives
+        w.append("app.log", " end\r\nERR 3");
+        w.read("app.log");
+        assert_eq!(w.file("app.log").offset, 22);
+
+        // No newline within the partial timeout: processed anyway
+        w.file("app.log").partial_since = Some(Instant::now() - Duration::from_secs(5));
+        flush_partials(&mut w.state, &w.matcher, &w.notifier);
+        assert_eq!(w.file("app.log").offset, 27);
+        assert!(w.file("app.log").partial_since.is_none());
+
+        let alerts = w.alerts().await;
+        assert_eq!(lines(&alerts), ["ERR 1", "ERR 2 end", "ERR 3"]);
+        assert_eq!(alerts[2]["line_no"], 4);
+    }
+
+    #[tokio::test]
+    async fn context_before_and_after_overlapping_hits() {
+        let read = ReadOptions { context_before: 2, context_after: 1, context_timeout: Duration::ZERO, ..Default::default() };
+        let mut w = Watch::new("tail-context", read);
+        w.append("app.log", "a\nb\nERR 1\nERR 2\nc\nERR 3\n");
+        w.read("app.log");
+        // The last hit waits for its "after" line, until the timeout
+        assert_eq!(w.file("app.log").pending.len(), 1);
+        flush_pending(&mut w.state, &w.matcher, &w.notifier, false);
+        assert!(w.file("app.log").pending.is_empty());
+
+        let alerts = w.alerts().await;
+        assert_eq!(lines(&alerts), ["ERR 1", "ERR 2", "ERR 3"]);
+        let context = |i: usize, side: &str| -> Vec<&str> {
+            alerts[i]["context"][side].as_array().unwrap().iter().map(|l| l["line"].as_str().unwrap()).collect()
+        };
+        assert_eq!((context(0, "before"), context(0, "after")), (vec!["a", "b"], vec!["ERR 2"]));
+        assert_eq!((context(1, "before"), context(1, "after")), (vec!["b", "ERR 1"], vec!["c"]));
+        assert_eq!((context(2, "before"), context(2, "after")), (vec!["ERR 2", "c"], vec![]));
+        assert_eq!(alerts[1]["context"]["before"][0]["line_no"], 2);
+    }
+
+    #[tokio::test]
+    async fn multiline_records_are_matched_whole() {
+        let spec = MultilineSpec { start: Some(r"^\d ".to_string()), continuation: None, indent: false, max_lines: 500, timeout: 0 };
+        let read = ReadOptions { multiline: Some(Multiline::new(&spec).unwrap()), ..Default::default() };
+        let mut w = Watch::new("tail-multiline", read);
+        w.append("app.log", "1 start\n  ERR inside\n2 ok\n3 ERR\n  more\n");
+        w.read("app.log");
+        // The last record may still grow, until the timeout
+        assert_eq!(w.file("app.log").record.as_ref().unwrap().lines.len(), 2);
+        flush_pending(&mut w.state, &w.matcher, &w.notifier, false);
+        assert!(w.file("app.log").record.is_none());
+
+        let alerts = w.alerts().await;
+        assert_eq!(lines(&alerts), ["1 start\n  ERR inside", "3 ERR\n  more"]);
+        assert_eq!(alerts[1]["line_no"], 4);
+    }
+
+    #[tokio::test]
+    async fn idle_handles_are_closed_and_reopened() {
+        let mut w = Watch::new("tail-idle", ReadOptions::default());
+        w.append("app.log", "ERR 1\n");
+        w.append("busy.log", "ERR 2");
+        w.append("old.log", "old\n");
+        for name in ["app.log", "busy.log", "old.log"] {
+            w.read(name);
+            age(w.file(name), HANDLE_IDLE);
+        }
+        drain_rotated(&mut w.state, &w.matcher, &w.notifier);
+        assert!(w.file("app.log").handle.is_none());
+        // Still holding back a partial line
+        assert!(w.file("busy.log").handle.is_some());
+
+        // A closed handle is only reopened on the same file
+        let old = FileIdentity::of(&w.path("old.log")).unwrap().key(&w.path("old.log"));
+        fs::remove_file(w.path("old.log")).unwrap();
+        w.append("old.log", "new\n");
+        assert!(w.state.files.get_mut(&old).unwrap().file().is_err());
+
+        w.append("app.log", "ERR 3\n");
+        assert!(w.file("app.log").file().is_ok());
+        w.read("app.log");
+        assert!(w.file("app.log").handle.is_some());
+
+        let alerts = w.alerts().await;
+        assert_eq!(lines(&alerts), ["ERR 1", "ERR 3"]);
+    }
+}
@@ -1,2 +1,6 @@
+pub mod checkpoint;
+pub mod dedup;
+pub mod encoding;
 pub mod events;
-pub mod files;
\ No newline at end of file
+pub mod files;
+pub mod multiline;
\ No newline at end of file
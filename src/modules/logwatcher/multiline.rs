@@ -0,0 +1,184 @@
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context};
+use regex::Regex;
+use serde::Deserialize;
+
+/// How lines are grouped into records (set exactly one of start/continuation/indent).
+#[derive(Debug, Deserialize, Clone)]
+pub struct MultilineSpec {
+    /// A line matching this regex starts a new record, others continue it
+    #[serde(default)]
+    pub start: Option<String>,
+    /// A line matching this regex continues the current record, others start a new one
+    #[serde(default)]
+    pub continuation: Option<String>,
+    /// Lines starting with a space or a tab continue the current record
+    #[serde(default)]
+    pub indent: bool,
+    /// A record is closed once it holds this many lines
+    #[serde(default = "default_max_lines")]
+    pub max_lines: usize,
+    /// Seconds without a new line after which the current record is closed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_max_lines() -> usize { 500 }
+fn default_timeout() -> u64 { 2 }
+
+#[derive(Debug, Clone)]
+enum Boundary {
+    Start(Regex),
+    Continuation(Regex),
+    Indent,
+}
+
+/// Compiled multi-line settings of a job.
+#[derive(Debug, Clone)]
+pub struct Multiline {
+    boundary: Boundary,
+    pub max_lines: usize,
+    pub timeout: Duration,
+}
+
+impl Multiline {
+    pub fn new(spec: &MultilineSpec) -> Result<Self> {
+        let boundary = match (&spec.start, &spec.continuation, spec.indent) {
+            (Some(re), None, false) => Boundary::Start(Regex::new(re).context("Invalid multiline 'start' regex")?),
+            (None, Some(re), false) => Boundary::Continuation(Regex::new(re).context("Invalid multiline 'continuation' regex")?),
+            (None, None, true) => Boundary::Indent,
+            _ => anyhow::bail!("multiline: set exactly one of 'start', 'continuation' or 'indent'"),
+        };
+        if spec.max_lines == 0 {
+            anyhow::bail!("multiline: 'max_lines' must be at least 1");
+        }
+        Ok(Self { boundary, max_lines: spec.max_lines, timeout: Duration::from_secs(spec.timeout) })
+    }
+
+    /// True if `line` belongs to the record in progress.
+    fn continues(&self, line: &str) -> bool {
+        match &self.boundary {
+            Boundary::Start(re) => !re.is_match(line),
+            Boundary::Continuation(re) => re.is_match(line),
+            Boundary::Indent => line.starts_with([' ', '\t']),
+        }
+    }
+
+    /// Add a line to the record in progress. Returns the records it completed, in order.
+    pub fn push(&self, current: &mut Option<Record>, line_no: u64, line: String, raw: Option<&[u8]>) -> Vec<Record> {
+        let mut done = Vec::new();
+        match current {
+            Some(rec) if self.continues(&line) => rec.append(line, raw),
+            _ => {
+                done.extend(current.take());
+                *current = Some(Record::new(line_no, line, raw));
+            }
+        }
+        if current.as_ref().is_some_and(|rec| rec.lines.len() >= self.max_lines) {
+            done.extend(current.take());
+        }
+        done
+    }
+
+    /// True if the record in progress has been waiting longer than the timeout.
+    pub fn overdue(&self, current: &Option<Record>) -> bool {
+        current.as_ref().is_some_and(|rec| rec.since.elapsed() >= self.timeout)
+    }
+}
+
+/// One or more lines handled as a single unit by the matcher.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Line number of the first line
+    pub line_no: u64,
+    pub lines: Vec<String>,
+    /// Undecoded bytes joined by "\n", only kept for raw matching
+    pub raw: Option<Vec<u8>>,
+    /// When the last line was added
+    since: Instant,
+}
+
+impl Record {
+    pub fn new(line_no: u64, line: String, raw: Option<&[u8]>) -> Self {
+        Self { line_no, lines: vec![line], raw: raw.map(<[u8]>::to_vec), since: Instant::now() }
+    }
+
+    fn append(&mut self, line: String, raw: Option<&[u8]>) {
+        self.lines.push(line);
+        if let (Some(buf), Some(raw)) = (self.raw.as_mut(), raw) {
+            buf.push(b'\n');
+            buf.extend_from_slice(raw);
+        }
+        self.since = Instant::now();
+    }
+
+    /// The record as text, lines joined by "\n".
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> MultilineSpec {
+        MultilineSpec { start: None, continuation: None, indent: false, max_lines: 500, timeout: 2 }
+    }
+
+    /// Feed `lines` and return the completed records, then the one in progress.
+    fn group(ml: &Multiline, lines: &[&str]) -> Vec<Vec<String>> {
+        let mut current = None;
+        let mut records = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            records.extend(ml.push(&mut current, i as u64 + 1, line.to_string(), None));
+        }
+        records.extend(current);
+        records.into_iter().map(|r| r.lines).collect()
+    }
+
+    #[test]
+    fn boundaries() {
+        let lines = ["2024 boom", "  at a()", "\tat b()", "2024 ok", "Caused by: x"];
+        let start = Multiline::new(&MultilineSpec { start: Some(r"^\d{4} ".to_string()), ..spec() }).unwrap();
+        assert_eq!(group(&start, &lines), [&lines[..3], &lines[3..]]);
+        let cont = Multiline::new(&MultilineSpec { continuation: Some(r"^\s|^Caused by".to_string()), ..spec() }).unwrap();
+        assert_eq!(group(&cont, &lines), [&lines[..3], &lines[3..]]);
+        let indent = Multiline::new(&MultilineSpec { indent: true, ..spec() }).unwrap();
+        assert_eq!(group(&indent, &lines), [&lines[..3], &lines[3..4], &lines[4..]]);
+    }
+
+    #[test]
+    fn max_lines_closes_the_record() {
+        let ml = Multiline::new(&MultilineSpec { indent: true, max_lines: 2, ..spec() }).unwrap();
+        let mut current = None;
+        assert!(ml.push(&mut current, 1, "a".to_string(), None).is_empty());
+        let done = ml.push(&mut current, 2, " b".to_string(), None);
+        assert_eq!(done[0].text(), "a\n b");
+        assert!(current.is_none());
+        // The next continuation line starts a record of its own
+        assert!(ml.push(&mut current, 3, " c".to_string(), None).is_empty());
+        assert_eq!(current.as_ref().unwrap().line_no, 3);
+    }
+
+    #[test]
+    fn raw_bytes_are_joined() {
+        let ml = Multiline::new(&MultilineSpec { indent: true, ..spec() }).unwrap();
+        let mut current = None;
+        ml.push(&mut current, 1, "a".to_string(), Some(b"a\xff"));
+        ml.push(&mut current, 2, " b".to_string(), Some(b" b"));
+        assert_eq!(current.unwrap().raw.unwrap(), b"a\xff\n b");
+    }
+
+    #[test]
+    fn invalid_specs_are_refused() {
+        for bad in [
+            spec(),
+            MultilineSpec { start: Some("a".to_string()), indent: true, ..spec() },
+            MultilineSpec { start: Some("(".to_string()), ..spec() },
+            MultilineSpec { indent: true, max_lines: 0, ..spec() },
+        ] {
+            assert!(Multiline::new(&bad).is_err(), "{bad:?}");
+        }
+    }
+}
@@ -8,7 +8,7 @@ use std::{collections::VecDeque, sync::{Arc, Mutex}, time::Duration};
 use tokio::{time::{interval, MissedTickBehavior}, sync::Semaphore};
 use log::{info,debug,trace,error};
 
-use crate::notifiers::Notifier;
+use crate::notifiers::{Detail, Format, Notifier, NotifyEvent, Severity};
 
 #[derive(Debug, Deserialize, Clone)]
 enum CheckResult {
@@ -46,7 +46,7 @@ fn vt_score(v: &Value) -> (u64, u64, String) {
 /// Check if the payload hash is inside VirusTotal database
 async fn check_hash(vt: &VtClient<'_>, hash: &str) -> Result<CheckResult> {
     debug!("Cheking if hash '{hash}' is inside VirusTotal database..");
-    match vt.get_report_file(&hash).await {
+    match vt.get_report_file(hash).await {
         Ok(v) => {
             // If error -> not found (file not published)
             if !v["error"].is_null() {
@@ -90,7 +90,7 @@ async fn check_hash(vt: &VtClient<'_>, hash: &str) -> Result<CheckResult> {
             let (mal, _total, ratio) = vt_score(&v);
             let reputation = v["data"]["attributes"]["reputation"].as_i64().unwrap_or(0);
 
-            return Ok(CheckResult::Found { filename, description, url, date, reputation, ratio, mal })
+            Ok(CheckResult::Found { filename, description, url, date, reputation, ratio, mal })
         },
         Err(err) => {
             // Détection propre du "not found"
@@ -100,10 +100,10 @@ async fn check_hash(vt: &VtClient<'_>, hash: &str) -> Result<CheckResult> {
                 || msg.contains("not found")
             {
                 info!("{hash}: not found on VirusTotal database");
-                return Ok(CheckResult::NotFound)
+                Ok(CheckResult::NotFound)
             } else {
                 error!("[Error] {msg}");
-                return Ok(CheckResult::TransientError)
+                Ok(CheckResult::TransientError)
             }
         }
     }   
@@ -154,11 +154,35 @@ pub async fn spawn_virustotal_watcher(
         match check_hash(&vt, &entry).await? {
             CheckResult::Found { filename, description, url, date, reputation, ratio, mal } => {
                 info!("Oh no! File published on VirusTotal!");
-                let _txt = format!("!dende-rs::virustotal-watcher::matched!\n\nFilename: {filename}\nDescription: {description}\nURL: {url}\nDate: {date}\nCommunity reputation: {reputation}\nDetection score: {ratio} ({mal} engines flagged)");
-                let _html = format!("<b>!dende-rs::virustotal-watcher::matched!</b>\n\n<b>Filename:<b> {filename}\n<b>Description:</b> {description}</b>\n<b>URL:</b> {url}\n</b>Date:</b> {date}\n<b>Community reputation:</b> {reputation}\n<b>Detection score:</b> {ratio} ({mal} engines flagged!)");
-                
-                trace!("\n{_txt}\n");
-                notifier.notify(&_txt);
+                let payload = serde_json::json!({
+                    "hash": entry,
+                    "filename": filename,
+                    "description": description,
+                    "url": url,
+                    "date": date,
+                    "reputation": reputation,
+                    "ratio": ratio,
+                    "malicious": mal,
+                });
+                let details = vec![
+                    Detail::text("Filename", filename),
+                    Detail::text("Description", description),
+                    Detail::text("Date", date),
+                    Detail::text("Community reputation", reputation.to_string()),
+                    Detail::text("Detection score", format!("{ratio} ({mal} engines flagged)")),
+                    Detail::link("VirusTotal", url),
+                ];
+
+                let ev = NotifyEvent {
+                    source: "virustotal-watcher".to_string(),
+                    severity: Severity::Critical,
+                    title: "!dende-rs::virustotal-watcher::matched!".to_string(),
+                    details,
+                    payload,
+                    ..Default::default()
+                };
+                trace!("\n{}\n", ev.render(Format::Plain));
+                notifier.notify_event(ev);
             }
             CheckResult::NotFound => {
                 queue.lock().unwrap().push_back(entry);
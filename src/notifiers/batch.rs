@@ -0,0 +1,260 @@
+use anyhow::{Result, Context};
+use chrono::{Local, NaiveTime};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::{Duration, Instant};
+
+use crate::notifiers::{Detail, Format, NotifyEvent, Severity};
+
+/// Digest settings (`batch` of a job, or an entry of its `batches`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchSpec {
+    /// The digest is sent once this many alerts are buffered
+    #[serde(default)]
+    pub max_events: Option<usize>,
+    /// Seconds after the first buffered alert before the digest is sent
+    #[serde(default)]
+    pub max_wait: Option<u64>,
+    /// Local time ("HH:MM") of a daily digest
+    #[serde(default)]
+    pub daily: Option<String>,
+    /// Lines shown per group of the digest
+    #[serde(default = "default_samples")]
+    pub samples: usize,
+}
+
+fn default_samples() -> usize { 3 }
+
+/// Alerts buffered for one sink until its digest is due.
+#[derive(Debug)]
+pub struct Batch {
+    max_events: Option<usize>,
+    max_wait: Option<Duration>,
+    daily: Option<NaiveTime>,
+    samples: usize,
+    events: Vec<NotifyEvent>,
+    /// When the first buffered alert came in
+    since: Option<Instant>,
+    next_daily: Option<Instant>,
+}
+
+impl Batch {
+    pub fn new(spec: &BatchSpec) -> Result<Self> {
+        if spec.max_events.is_none() && spec.max_wait.is_none() && spec.daily.is_none() {
+            anyhow::bail!("batch: set 'max_events', 'max_wait' or 'daily'");
+        }
+        if spec.max_events == Some(0) {
+            anyhow::bail!("batch: 'max_events' must be at least 1");
+        }
+        if spec.max_wait == Some(0) {
+            anyhow::bail!("batch: 'max_wait' must be at least 1 second");
+        }
+        let daily = spec.daily.as_deref()
+            .map(|t| NaiveTime::parse_from_str(t.trim(), "%H:%M"))
+            .transpose()
+            .context("batch: 'daily' expects a time like \"08:30\"")?;
+        Ok(Self {
+            max_events: spec.max_events,
+            max_wait: spec.max_wait.map(Duration::from_secs),
+            daily,
+            samples: spec.samples,
+            events: Vec::new(),
+            since: None,
+            next_daily: daily.map(next_daily),
+        })
+    }
+
+    pub fn push(&mut self, ev: NotifyEvent) {
+        self.since.get_or_insert_with(Instant::now);
+        self.events.push(ev);
+    }
+
+    /// When the notifier must wake up for this batch.
+    pub fn deadline(&self) -> Option<Instant> {
+        let wait = self.since.zip(self.max_wait).map(|(since, wait)| since + wait);
+        [wait, self.next_daily].into_iter().flatten().min()
+    }
+
+    /// The digest of the buffered alerts, if full or due.
+    pub fn due(&mut self) -> Option<NotifyEvent> {
+        let now = Instant::now();
+        let full = self.max_events.is_some_and(|max| self.events.len() >= max);
+        let waited = self.since.zip(self.max_wait).is_some_and(|(since, wait)| now >= since + wait);
+        let daily = self.next_daily.is_some_and(|at| now >= at);
+        if daily {
+            // Next day's run is scheduled even when there was nothing to send
+            self.next_daily = self.daily.map(next_daily);
+        }
+        if full || waited || daily { self.take() } else { None }
+    }
+
+    /// The digest of whatever is buffered (e.g. on shutdown), a lone alert as is.
+    pub fn take(&mut self) -> Option<NotifyEvent> {
+        self.since = None;
+        match self.events.len() {
+            0 => None,
+            1 => self.events.pop(),
+            _ => Some(digest(&std::mem::take(&mut self.events), self.samples)),
+        }
+    }
+}
+
+/// Next local occurrence of `at`.
+fn next_daily(at: NaiveTime) -> Instant {
+    let now = Local::now().naive_local();
+    let today = now.date().and_time(at);
+    let next = if today > now { today } else { today + chrono::Duration::days(1) };
+    Instant::now() + (next - now).to_std().unwrap_or_default()
+}
+
+/// Alerts of a digest sharing a file (or source) and rules (or title).
+struct Group {
+    file: String,
+    rules: String,
+    count: usize,
+    samples: Vec<String>,
+}
+
+/// One line of an alert: the matched line when there is one, its text otherwise.
+fn sample(ev: &NotifyEvent) -> String {
+    let line = match (ev.payload.get("line"), ev.payload.get("line_no")) {
+        (Some(Value::String(line)), Some(n)) => format!("{n}: {line}"),
+        (Some(Value::String(line)), None) => line.clone(),
+        _ => ev.text.clone().unwrap_or_else(|| ev.render(Format::Plain))
+            .lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join(" | "),
+    };
+    if line.chars().count() > 300 {
+        return line.chars().take(299).chain(std::iter::once('…')).collect();
+    }
+    line
+}
+
+/// One alert summing up `events`: counts and first samples per file and rules.
+pub fn digest(events: &[NotifyEvent], samples: usize) -> NotifyEvent {
+    let mut groups: Vec<Group> = Vec::new();
+    for ev in events {
+        let file = ev.payload.get("file").and_then(Value::as_str).unwrap_or(&ev.source).to_string();
+        let rules = match ev.payload.get("rules").and_then(Value::as_array) {
+            Some(rules) => rules.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", "),
+            None => ev.headline().to_string(),
+        };
+        let i = match groups.iter().position(|g| g.file == file && g.rules == rules) {
+            Some(i) => i,
+            None => {
+                groups.push(Group { file, rules, count: 0, samples: Vec::new() });
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[i];
+        group.count += 1;
+        if group.samples.len() < samples {
+            group.samples.push(sample(ev));
+        }
+    }
+
+    let mut details = vec![Detail::text("Alerts", format!("{} in {} group(s)", events.len(), groups.len()))];
+    for g in &groups {
+        let mut lines = g.samples.clone();
+        if g.count > g.samples.len() {
+            lines.push(format!("… and {} more", g.count - g.samples.len()));
+        }
+        details.push(Detail::code(&format!("{} [{}] x{}", g.file, g.rules, g.count), lines.join("\n")));
+    }
+    let payload = serde_json::json!({
+        "count": events.len(),
+        "groups": groups.iter().map(|g| serde_json::json!({
+            "file": g.file,
+            "rules": g.rules,
+            "count": g.count,
+            "samples": g.samples,
+        })).collect::<Vec<_>>(),
+    });
+
+    NotifyEvent {
+        job: events.first().and_then(|ev| ev.job.clone()),
+        source: "digest".to_string(),
+        severity: events.iter().map(|ev| ev.severity).max().unwrap_or(Severity::Normal),
+        title: "!dende-rs::digest!".to_string(),
+        details,
+        payload,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matched(file: &str, rule: &str, line: &str, severity: Severity) -> NotifyEvent {
+        NotifyEvent {
+            job: Some("logs".to_string()),
+            source: "log-watcher".to_string(),
+            severity,
+            title: "matched".to_string(),
+            payload: json!({ "file": file, "rules": [rule], "line": line, "line_no": 7 }),
+            ..Default::default()
+        }
+    }
+
+    fn spec(max_events: Option<usize>) -> BatchSpec {
+        BatchSpec { max_events, max_wait: None, daily: None, samples: 2 }
+    }
+
+    #[test]
+    fn digest_groups_by_file_and_rules() {
+        let events = [
+            matched("/a.log", "oom", "killed 1", Severity::High),
+            matched("/a.log", "oom", "killed 2", Severity::Normal),
+            matched("/b.log", "oom", "killed 3", Severity::Normal),
+            matched("/a.log", "oom", "killed 4", Severity::Critical),
+            NotifyEvent { msg: "disk full\n\n on /var".to_string(), source: "disk".to_string(), ..Default::default() },
+        ];
+        let d = digest(&events, 2);
+        assert_eq!(d.title, "!dende-rs::digest!");
+        assert_eq!(d.source, "digest");
+        assert_eq!(d.job.as_deref(), Some("logs"));
+        assert_eq!(d.severity, Severity::Critical);
+        assert_eq!(d.payload["count"], 5);
+        let groups = d.payload["groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0], json!({ "file": "/a.log", "rules": "oom", "count": 3, "samples": ["7: killed 1", "7: killed 2"] }));
+        assert_eq!(groups[2], json!({ "file": "disk", "rules": "disk full", "count": 1, "samples": ["disk full | on /var"] }));
+
+        let labels: Vec<_> = d.details.iter().map(|d| d.label.as_str()).collect();
+        assert_eq!(labels, ["Alerts", "/a.log [oom] x3", "/b.log [oom] x1", "disk [disk full] x1"]);
+        assert_eq!(d.details[0].value, "5 in 3 group(s)");
+        assert!(d.details[1].value.ends_with("… and 1 more"));
+    }
+
+    #[test]
+    fn long_samples_are_cut() {
+        let d = digest(&vec![matched("/a", "r", &"x".repeat(500), Severity::Low); 2], 1);
+        let sample = d.payload["groups"][0]["samples"][0].as_str().unwrap();
+        assert_eq!(sample.chars().count(), 300);
+        assert!(sample.ends_with('…'));
+    }
+
+    #[test]
+    fn batch_sends_when_full_and_alone_as_is() {
+        let mut batch = Batch::new(&spec(Some(2))).unwrap();
+        batch.push(matched("/a", "r", "one", Severity::Low));
+        assert!(batch.due().is_none());
+        batch.push(matched("/a", "r", "two", Severity::Low));
+        assert_eq!(batch.due().unwrap().payload["count"], 2);
+        assert!(batch.deadline().is_none());
+
+        batch.push(matched("/a", "r", "three", Severity::Low));
+        assert_eq!(batch.take().unwrap().payload["line"], "three");
+        assert!(batch.take().is_none());
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        assert!(Batch::new(&spec(None)).is_err());
+        assert!(Batch::new(&spec(Some(0))).is_err());
+        assert!(Batch::new(&BatchSpec { daily: Some("25:00".to_string()), ..spec(None) }).is_err());
+        assert!(Batch::new(&BatchSpec { max_wait: Some(0), ..spec(None) }).is_err());
+        assert!(Batch::new(&BatchSpec { daily: Some("08:30".to_string()), ..spec(None) }).unwrap().deadline().is_some());
+    }
+}
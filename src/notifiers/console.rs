@@ -1,17 +1,27 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use log::info;
 
+use crate::notifiers::{Format, NotifyEvent, Sink};
+
 #[derive(Clone, Debug)]
 pub struct ConsoleSink {
-    _tag: String,
+    tag: String,
 }
 
 impl ConsoleSink {
-    pub fn new(_tag: String) -> Self { Self { _tag } }
+    pub fn new(tag: String) -> Self { Self { tag } }
+}
 
-    pub async fn send(&self, text: &str) -> Result<()> {
+#[async_trait]
+impl Sink for ConsoleSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
         info!("Sending notification from console..");
-        println!("\n{text}\n");
+        println!("\n{}\n", ev.render(Format::Plain));
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn name(&self) -> String {
+        format!("console:{}", self.tag)
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::{info,debug};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::notifiers::render::{clip, code_block, md_escape};
+use crate::notifiers::{http_client, Delivered, DetailKind, NotifyEvent, Severity, Sink};
+
+/// A named Discord webhook (entry of the top-level `discord` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscordWebhook {
+    pub url: String,
+    /// Override the name shown as the author of the message
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+// Discord limits (characters)
+const TITLE_MAX: usize = 256;
+const DESCRIPTION_MAX: usize = 4096;
+const FIELD_NAME_MAX: usize = 256;
+const FIELD_VALUE_MAX: usize = 1024;
+const FOOTER_MAX: usize = 2048;
+const FIELDS_MAX: usize = 25;
+/// Sum of all the texts of the embeds of one message
+const MESSAGE_MAX: usize = 6000;
+
+#[derive(Debug)]
+pub struct DiscordSink {
+    client: reqwest::Client,
+    name: String,
+    hook: DiscordWebhook,
+    /// Embeds posted, so a retry does not repost the first embeds
+    delivered: Delivered<usize>,
+}
+
+impl DiscordSink {
+    pub fn new(name: &str, hook: &DiscordWebhook) -> Result<Self> {
+        Ok(Self { client: http_client()?, name: name.to_string(), hook: hook.clone(), delivered: Delivered::default() })
+    }
+}
+
+/// Embed colour of a severity: grey, blue, orange, red.
+fn colour(severity: Severity) -> u32 {
+    match severity {
+        Severity::Low => 0x80_80_80,
+        Severity::Normal => 0x39_4e_ff,
+        Severity::High => 0xf0_8c_00,
+        Severity::Critical => 0xd0_00_00,
+    }
+}
+
+/// One or more embeds, each one small enough to be sent in its own message.
+fn embeds(ev: &NotifyEvent) -> Vec<Value> {
+    let title = clip(ev.headline(), TITLE_MAX);
+    let footer = clip(&format!("dende-rs {}", ev.job.as_deref().unwrap_or_default()), FOOTER_MAX);
+    let link = ev.details.iter().find(|d| d.kind == DetailKind::Link).map(|d| d.value.clone());
+    let new_embed = |part: usize| {
+        let mut embed = json!({
+            "title": if part == 0 { title.clone() } else { clip(&format!("{title} (cont.)"), TITLE_MAX) },
+            "color": colour(ev.severity),
+            "footer": { "text": footer },
+            "fields": [],
+        });
+        if let Some(url) = &link {
+            embed["url"] = json!(url);
+        }
+        embed
+    };
+
+    if let Some(text) = &ev.text {
+        let mut embed = new_embed(0);
+        embed["description"] = json!(clip(text, DESCRIPTION_MAX - TITLE_MAX - FOOTER_MAX));
+        return vec![embed];
+    }
+    if ev.details.is_empty() {
+        let mut embed = new_embed(0);
+        embed["description"] = json!(code_block(&ev.msg, DESCRIPTION_MAX - TITLE_MAX - FOOTER_MAX));
+        return vec![embed];
+    }
+
+    let fields = ev.job.iter()
+        .map(|j| ("Job".to_string(), j.clone(), true))
+        .chain(ev.details.iter().map(|d| {
+            let value = match d.kind {
+                DetailKind::Text => clip(&d.value, FIELD_VALUE_MAX),
+                DetailKind::Code => code_block(&d.value, FIELD_VALUE_MAX),
+                DetailKind::Link => clip(&d.value, FIELD_VALUE_MAX),
+            };
+            (clip(&d.label, FIELD_NAME_MAX), value, d.kind == DetailKind::Text)
+        }));
+
+    let base = title.chars().count() + footer.chars().count() + " (cont.)".len();
+    let (mut out, mut embed, mut size) = (Vec::new(), new_embed(0), base);
+    for (name, value, inline) in fields {
+        let len = name.chars().count() + value.chars().count();
+        let full = embed["fields"].as_array().is_some_and(|f| f.len() >= FIELDS_MAX);
+        if full || size + len > MESSAGE_MAX {
+            out.push(std::mem::replace(&mut embed, new_embed(out.len() + 1)));
+            size = base;
+        }
+        if let Some(f) = embed["fields"].as_array_mut() {
+            f.push(json!({ "name": name, "value": value, "inline": inline }));
+        }
+        size += len;
+    }
+    out.push(embed);
+    out
+}
+
+#[async_trait]
+impl Sink for DiscordSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from discord..");
+        let start = self.delivered.get(ev.id);
+        for (i, embed) in embeds(ev).into_iter().enumerate().skip(start) {
+            let mut payload = json!({ "embeds": [embed] });
+            if let Some(username) = &self.hook.username {
+                payload["username"] = json!(username);
+            }
+            if let Some(avatar) = &self.hook.avatar_url {
+                payload["avatar_url"] = json!(avatar);
+            }
+            self.client.post(&self.hook.url).json(&payload).send().await?.error_for_status()?;
+            self.delivered.set(ev.id, i + 1);
+        }
+        debug!("Sent by discord to {}", self.name);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("discord:{}", self.name)
+    }
+
+    /// Template variables cannot add markup or links.
+    fn escape(&self, value: &str) -> String {
+        md_escape(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::Detail;
+    use crate::notifiers::testing::http_server;
+
+    /// An alert too large for one Discord message.
+    fn big_event() -> NotifyEvent {
+        NotifyEvent {
+            id: 42,
+            title: "big".to_string(),
+            severity: Severity::High,
+            details: (0..20).map(|i| Detail::code(&format!("part {i}"), "x".repeat(900))).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn large_alerts_are_split_within_limits() {
+        let embeds = embeds(&big_event());
+        assert!(embeds.len() > 1);
+        for e in &embeds {
+            let fields = e["fields"].as_array().unwrap();
+            assert!(fields.len() <= FIELDS_MAX);
+            let size: usize = fields.iter()
+                .map(|f| f["name"].as_str().unwrap().chars().count() + f["value"].as_str().unwrap().chars().count())
+                .sum();
+            assert!(size <= MESSAGE_MAX);
+        }
+    }
+
+    #[test]
+    fn colour_follows_severity() {
+        let mut ev = big_event();
+        assert_eq!(embeds(&ev)[0]["color"], json!(colour(Severity::High)));
+        ev.severity = Severity::Low;
+        assert_eq!(embeds(&ev)[0]["color"], json!(colour(Severity::Low)));
+        assert_ne!(colour(Severity::Normal), colour(Severity::Critical));
+    }
+
+    #[tokio::test]
+    async fn retry_does_not_repost_embeds() {
+        let (url, requests) = http_server(vec![204, 500]);
+        let sink = DiscordSink::new("ops", &DiscordWebhook { url, username: None, avatar_url: None }).unwrap();
+        let ev = big_event();
+        let count = embeds(&ev).len();
+        assert!(sink.send(&ev).await.is_err());
+        sink.send(&ev).await.unwrap();
+
+        let titles: Vec<String> = requests.lock().unwrap().iter()
+            .map(|r| r.json()["embeds"][0]["fields"][0]["name"].as_str().unwrap_or_default().to_string())
+            .collect();
+        // First embed once, second one twice (failed then retried), then the rest
+        assert_eq!(titles.len(), count + 1);
+        assert_eq!(titles[1], titles[2]);
+        assert_eq!(titles.iter().filter(|t| **t == titles[0]).count(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{info,debug};
+use serde::Deserialize;
+
+use crate::notifiers::template::Template;
+use crate::notifiers::{Format, NotifyEvent, Sink};
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS (port 587)
+    #[default]
+    Starttls,
+    /// TLS from the first byte (port 465)
+    Tls,
+    /// No encryption at all, only for local relays and test servers (port 25)
+    None,
+}
+
+/// SMTP server used by the `email:` sinks (top-level `smtp` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    /// Defaults to the usual port of `security`
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    /// AUTH is only attempted when both username and password are set
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Sender, e.g. `dende-rs <alerts@example.com>`
+    pub from: String,
+    /// Subject template, see `Template`
+    #[serde(default = "default_subject")]
+    pub subject: String,
+}
+
+fn default_subject() -> String { "[dende-rs] {{title}}".to_string() }
+
+pub struct EmailSink {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Mailbox,
+    subject: Template,
+}
+
+impl std::fmt::Debug for EmailSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EmailSink(to={})", self.to)
+    }
+}
+
+impl EmailSink {
+    pub fn new(settings: &SmtpSettings, to: &str) -> Result<Self> {
+        let to: Mailbox = to.parse().with_context(|| format!("Invalid email address '{to}'"))?;
+        let from: Mailbox = settings.from.parse()
+            .with_context(|| format!("Invalid smtp 'from' address '{}'", settings.from))?;
+
+        let mut builder = match settings.security {
+            SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host),
+        };
+        if let Some(port) = settings.port {
+            builder = builder.port(port);
+        }
+        if let (Some(user), Some(pass)) = (&settings.username, &settings.password) {
+            builder = builder.credentials(Credentials::new(user.clone(), pass.clone()));
+        }
+        let subject = Template::parse(&settings.subject).context("Invalid smtp 'subject' template")?;
+        Ok(Self { transport: builder.build(), from, to, subject })
+    }
+
+}
+
+#[async_trait]
+impl Sink for EmailSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from email..");
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            // Header values must stay on one line
+            .subject(self.subject.render_event(ev, |v| v.replace(['\r', '\n'], " ")))
+            .multipart(MultiPart::alternative_plain_html(ev.render(Format::Plain), ev.render(Format::Html)))?;
+        self.transport.send(message).await?;
+        debug!("Sent by email to {}", self.to);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("email:{}", self.to.email)
+    }
+
+    /// Connect to the server without sending anything.
+    async fn health_check(&self) -> Result<()> {
+        if !self.transport.test_connection().await? {
+            anyhow::bail!("SMTP server did not answer");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use crate::notifiers::Detail;
+
+    /// Minimal SMTP server for one connection: records the client side of the
+    /// dialogue, rejects the message after DATA when `reject` is set.
+    fn smtp_server(reject: bool) -> (u16, Arc<Mutex<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let log = Arc::new(Mutex::new(String::new()));
+        let seen = log.clone();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut out = stream.try_clone().unwrap();
+            let mut reply = |s: &str| out.write_all(format!("{s}\r\n").as_bytes()).unwrap();
+            reply("220 test ESMTP");
+            let mut data = false;
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else { break };
+                seen.lock().unwrap().push_str(&format!("{line}\n"));
+                if data {
+                    if line == "." {
+                        data = false;
+                        reply(if reject { "554 5.7.1 Message rejected" } else { "250 2.0.0 queued" });
+                    }
+                    continue;
+                }
+                match line.get(..4).unwrap_or_default().to_ascii_uppercase().as_str() {
+                    "EHLO" => { reply("250-test"); reply("250 AUTH PLAIN LOGIN"); }
+                    "AUTH" => reply("235 2.7.0 Authentication successful"),
+                    "DATA" => { data = true; reply("354 go ahead"); }
+                    "QUIT" => { reply("221 bye"); break; }
+                    _ => reply("250 ok"),
+                }
+            }
+        });
+        (port, log)
+    }
+
+    fn settings(port: u16, auth: bool) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: auth.then(|| "user".to_string()),
+            password: auth.then(|| "secret".to_string()),
+            from: "dende-rs <alerts@example.com>".to_string(),
+            subject: "[dende-rs] {{title}} user={{user}}".to_string(),
+        }
+    }
+
+    fn event() -> NotifyEvent {
+        NotifyEvent {
+            title: "Login failed".to_string(),
+            fields: [("user".to_string(), "root".to_string())].into(),
+            details: vec![Detail::text("File", "/var/log/auth.log:12"), Detail::code("Content matched", "a < b")],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_multipart_with_templated_subject() {
+        let (port, log) = smtp_server(false);
+        let sink = EmailSink::new(&settings(port, false), "ops@example.com").unwrap();
+        sink.send(&event()).await.unwrap();
+
+        let log = log.lock().unwrap();
+        assert!(log.contains("Subject: [dende-rs] Login failed user=root"), "{log}");
+        assert!(log.contains("multipart/alternative"), "{log}");
+        assert!(log.contains("Content-Type: text/plain"), "{log}");
+        assert!(log.contains("Content-Type: text/html"), "{log}");
+        assert!(log.contains("&lt;"), "HTML part must be escaped: {log}");
+        assert!(!log.contains("AUTH"), "no AUTH without credentials: {log}");
+    }
+
+    #[tokio::test]
+    async fn authenticates_when_credentials_are_set() {
+        let (port, log) = smtp_server(false);
+        let sink = EmailSink::new(&settings(port, true), "ops@example.com").unwrap();
+        sink.send(&event()).await.unwrap();
+        assert!(log.lock().unwrap().contains("AUTH"));
+    }
+
+    #[tokio::test]
+    async fn rejected_message_is_an_error() {
+        let (port, _) = smtp_server(true);
+        let sink = EmailSink::new(&settings(port, false), "ops@example.com").unwrap();
+        let err = sink.send(&event()).await.unwrap_err();
+        assert!(err.to_string().contains("rejected") || format!("{err:?}").contains("554"), "{err:?}");
+    }
+
+    #[test]
+    fn invalid_subject_template_is_refused() {
+        let mut s = settings(25, false);
+        s.subject = "{{title".to_string();
+        assert!(EmailSink::new(&s, "ops@example.com").is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+use log::{info,debug,warn};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::notifiers::{Format, NotifyEvent, Sink};
+
+/// A named program (entry of the top-level `exec` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecSpec {
+    /// Program to run, looked up in `PATH` when not a path (no shell involved)
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Seconds before the program is killed and the attempt counted as failed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 { 30 }
+
+#[derive(Debug)]
+pub struct ExecSink {
+    name: String,
+    spec: ExecSpec,
+}
+
+impl ExecSink {
+    pub fn new(name: &str, spec: &ExecSpec) -> Result<Self> {
+        if spec.command.trim().is_empty() {
+            anyhow::bail!("Exec '{name}': empty 'command'");
+        }
+        if spec.timeout == 0 {
+            anyhow::bail!("Exec '{name}': 'timeout' must be at least 1 second");
+        }
+        Ok(Self { name: name.to_string(), spec: spec.clone() })
+    }
+}
+
+/// `line_no` -> `LINE_NO`, `client-ip` -> `CLIENT_IP`.
+fn env_name(key: &str) -> String {
+    key.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect()
+}
+
+/// Scalars as is, arrays of scalars comma-separated, nothing for objects.
+fn env_value(v: &Value) -> Option<String> {
+    match v {
+        Value::Null | Value::Object(_) => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Array(items) => {
+            let items: Option<Vec<_>> = items.iter()
+                .map(|i| if i.is_array() || i.is_object() { None } else { env_value(i) })
+                .collect();
+            items.map(|i| i.join(","))
+        }
+        other => Some(other.to_string()),
+    }
+}
+
+/// Longest `DENDE_*` value (bytes); the whole alert is on stdin anyway.
+const ENV_MAX: usize = 4096;
+
+/// Without NUL bytes (refused by `execve`) and cut to `ENV_MAX` bytes, ending with `…` when cut.
+fn env_safe(value: String) -> String {
+    let value = if value.contains('\0') { value.replace('\0', "") } else { value };
+    if value.len() <= ENV_MAX {
+        return value;
+    }
+    let mut end = ENV_MAX - '…'.len_utf8();
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &value[..end])
+}
+
+/// `DENDE_*` variables: common fields, payload scalars and `DENDE_FIELD_<NAME>` per capture.
+fn env_vars(ev: &NotifyEvent) -> Vec<(String, String)> {
+    let mut vars = vec![
+        ("DENDE_ID".to_string(), ev.id.to_string()),
+        ("DENDE_JOB".to_string(), ev.job.clone().unwrap_or_default()),
+        ("DENDE_SOURCE".to_string(), ev.source.clone()),
+        ("DENDE_SEVERITY".to_string(), env_value(&serde_json::json!(ev.severity)).unwrap_or_default()),
+        ("DENDE_TITLE".to_string(), ev.headline().to_string()),
+        ("DENDE_MESSAGE".to_string(), ev.render(Format::Plain)),
+    ];
+    if let Value::Object(payload) = &ev.payload {
+        for (k, v) in payload {
+            if let Some(v) = env_value(v) {
+                vars.push((format!("DENDE_{}", env_name(k)), v));
+            }
+        }
+    }
+    for (k, v) in &ev.fields {
+        vars.push((format!("DENDE_FIELD_{}", env_name(k)), v.clone()));
+    }
+    vars.into_iter().map(|(k, v)| (k, env_safe(v))).collect()
+}
+
+#[async_trait]
+impl Sink for ExecSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from exec..");
+        let mut child = Command::new(&self.spec.command)
+            .args(&self.spec.args)
+            .envs(env_vars(ev))
+            .envs(&self.spec.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Exec '{}': spawning '{}'", self.name, self.spec.command))?;
+
+        let input = format!("{}\n", ev.render(Format::Json));
+        let mut stdin = child.stdin.take().context("stdin not piped")?;
+        let run = async move {
+            // The program may exit without reading its input
+            if let Err(e) = stdin.write_all(input.as_bytes()).await {
+                debug!("Exec: stdin not fully written: {e}");
+            }
+            drop(stdin);
+            child.wait_with_output().await
+        };
+        // On timeout the child is dropped, hence killed
+        let out = tokio::time::timeout(Duration::from_secs(self.spec.timeout), run).await
+            .map_err(|_| anyhow::anyhow!("Exec '{}': killed after {}s", self.name, self.spec.timeout))??;
+
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let stderr = stderr.trim();
+        if !out.status.success() {
+            anyhow::bail!("Exec '{}': {} {stderr}", self.name, out.status);
+        }
+        if !stderr.is_empty() {
+            warn!("Exec '{}' stderr: {stderr}", self.name);
+        }
+        debug!("Sent by exec to {}", self.name);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("exec:{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_values_drop_nul_and_are_capped() {
+        assert_eq!(env_safe("a\0b".to_string()), "ab");
+        assert_eq!(env_safe("short".to_string()), "short");
+        let cut = env_safe("é".repeat(ENV_MAX));
+        assert!(cut.len() <= ENV_MAX);
+        assert!(cut.ends_with('…'));
+        assert!(cut.trim_end_matches('…').chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn env_vars_of_an_event() {
+        let ev = NotifyEvent {
+            id: 7,
+            title: "bad\0line".to_string(),
+            fields: [("client-ip".to_string(), "10.0.0.1".to_string())].into(),
+            payload: serde_json::json!({ "line": "x".repeat(10 * ENV_MAX), "rules": ["a", "b"], "nested": {} }),
+            ..Default::default()
+        };
+        let vars: BTreeMap<_, _> = env_vars(&ev).into_iter().collect();
+        assert_eq!(vars["DENDE_ID"], "7");
+        assert_eq!(vars["DENDE_TITLE"], "badline");
+        assert_eq!(vars["DENDE_FIELD_CLIENT_IP"], "10.0.0.1");
+        assert_eq!(vars["DENDE_RULES"], "a,b");
+        assert!(vars["DENDE_LINE"].len() <= ENV_MAX);
+        assert!(!vars.contains_key("DENDE_NESTED"));
+        assert!(vars.values().all(|v| !v.contains('\0')));
+    }
+
+    fn sh(script: &str, timeout: u64, env: &[(&str, &str)]) -> ExecSink {
+        let spec = ExecSpec {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            timeout,
+        };
+        ExecSink::new("test", &spec).unwrap()
+    }
+
+    #[tokio::test]
+    async fn alert_on_stdin_and_in_env() {
+        let out = std::env::temp_dir().join(format!("dende-rs-exec-{}.json", std::process::id()));
+        let out_str = out.to_str().unwrap();
+        let sink = sh(r#"cat > "$OUT"; printf %s "$DENDE_TITLE" > "$OUT.title""#, 5, &[("OUT", out_str)]);
+        let ev = NotifyEvent { id: 5, title: "disk full".to_string(), payload: serde_json::json!({ "line": "sda1 100%" }), ..Default::default() };
+        sink.send(&ev).await.unwrap();
+
+        let written = std::fs::read_to_string(&out).unwrap();
+        assert!(written.ends_with('\n'));
+        let json: Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(json["id"], 5);
+        assert_eq!(json["title"], "disk full");
+        assert_eq!(json["line"], "sda1 100%");
+        assert_eq!(std::fs::read_to_string(format!("{out_str}.title")).unwrap(), "disk full");
+        std::fs::remove_file(&out).unwrap();
+        std::fs::remove_file(format!("{out_str}.title")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failing_program_is_an_error() {
+        let err = sh("echo nope >&2; exit 3", 5, &[]).send(&NotifyEvent::default()).await.unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("exit status: 3") && err.contains("nope"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn slow_program_is_killed() {
+        let started = std::time::Instant::now();
+        let sink = ExecSink::new("slow", &ExecSpec { command: "sleep".to_string(), args: vec!["5".to_string()], env: BTreeMap::new(), timeout: 1 }).unwrap();
+        let err = sink.send(&NotifyEvent::default()).await.unwrap_err();
+        assert!(err.to_string().contains("killed after 1s"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn invalid_specs_are_refused() {
+        let spec = ExecSpec { command: " ".to_string(), args: vec![], env: BTreeMap::new(), timeout: 1 };
+        assert!(ExecSink::new("x", &spec).is_err());
+        assert!(ExecSink::new("x", &ExecSpec { command: "true".to_string(), timeout: 0, ..spec }).is_err());
+    }
+}
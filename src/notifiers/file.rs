@@ -0,0 +1,171 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use log::{info,debug};
+use serde::Deserialize;
+
+use crate::notifiers::{Format, NotifyEvent, Sink};
+
+/// Rotation of the `file:` sinks (top-level `file` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileSinkSettings {
+    /// Rotate once the file reaches this size (bytes, 0 = never)
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotate once the file is older than this (seconds, 0 = never)
+    #[serde(default)]
+    pub max_age: u64,
+    /// Rotated files kept as `<path>.1` (newest) .. `<path>.<keep>`
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+impl Default for FileSinkSettings {
+    fn default() -> Self {
+        Self { max_bytes: default_max_bytes(), max_age: 0, keep: default_keep() }
+    }
+}
+
+fn default_max_bytes() -> u64 { 10 * 1024 * 1024 }
+fn default_keep() -> usize { 5 }
+
+/// An open JSONL file and its rotation state.
+struct JsonlFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+    settings: FileSinkSettings,
+}
+
+impl JsonlFile {
+    fn open(path: &Path, settings: &FileSinkSettings) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Opening {}", path.display()))?;
+        let meta = file.metadata()?;
+        let opened = meta.created().or_else(|_| meta.modified()).unwrap_or_else(|_| SystemTime::now());
+        Ok(Self { path: path.to_path_buf(), file, size: meta.len(), opened, settings: settings.clone() })
+    }
+
+    fn due(&self) -> bool {
+        let s = &self.settings;
+        (s.max_bytes > 0 && self.size >= s.max_bytes)
+            || (s.max_age > 0 && self.opened.elapsed().unwrap_or_default() >= Duration::from_secs(s.max_age))
+    }
+
+    /// `<path>.N` -> `<path>.N+1` (the last one dropped), `<path>` -> `<path>.1`, then reopen.
+    fn rotate(&mut self) -> Result<()> {
+        let numbered = |n: usize| PathBuf::from(format!("{}.{n}", self.path.display()));
+        if self.settings.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(numbered(self.settings.keep));
+            for n in (1..self.settings.keep).rev() {
+                let _ = fs::rename(numbered(n), numbered(n + 1));
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        info!("Rotated {}", self.path.display());
+        let settings = self.settings.clone();
+        *self = Self::open(&self.path, &settings)?;
+        self.opened = SystemTime::now();
+        Ok(())
+    }
+
+    fn append(&mut self, line: &str) -> Result<()> {
+        if self.size > 0 && self.due() {
+            self.rotate()?;
+        }
+        // One write per record so concurrent readers never see half a line
+        self.file.write_all(format!("{line}\n").as_bytes())?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+/// Files opened by the sinks, shared when several jobs write to the same path.
+fn open_files() -> &'static Mutex<HashMap<PathBuf, Arc<Mutex<JsonlFile>>>> {
+    static FILES: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<JsonlFile>>>>> = OnceLock::new();
+    FILES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub struct FileSink {
+    path: PathBuf,
+    file: Arc<Mutex<JsonlFile>>,
+}
+
+impl std::fmt::Debug for FileSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FileSink(path={})", self.path.display())
+    }
+}
+
+impl FileSink {
+    pub fn new(settings: &FileSinkSettings, path: &str) -> Result<Self> {
+        if path.is_empty() {
+            anyhow::bail!("Empty file sink path");
+        }
+        let path = PathBuf::from(path);
+        let mut files = open_files().lock().unwrap_or_else(|e| e.into_inner());
+        let file = match files.get(&path) {
+            Some(f) => f.clone(),
+            None => {
+                let f = Arc::new(Mutex::new(JsonlFile::open(&path, settings)?));
+                files.insert(path.clone(), f.clone());
+                f
+            }
+        };
+        Ok(Self { path, file })
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from file..");
+        let line = ev.render(Format::Json);
+        // Writes and rotations block: keep them (and the file lock) off the runtime threads
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || file.lock().unwrap_or_else(|e| e.into_inner()).append(&line))
+            .await
+            .context("File sink writer panicked")??;
+        debug!("Written to {}", self.path.display());
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn appends_records_and_rotates() {
+        let dir = std::env::temp_dir().join(format!("dende-rs-file-sink-{}", std::process::id()));
+        let path = dir.join("alerts.jsonl");
+        let settings = FileSinkSettings { max_bytes: 1, max_age: 0, keep: 1 };
+        let sink = FileSink::new(&settings, path.to_str().unwrap()).unwrap();
+        for title in ["first", "second", "third"] {
+            sink.send(&NotifyEvent { title: title.to_string(), ..Default::default() }).await.unwrap();
+        }
+
+        let current = fs::read_to_string(&path).unwrap();
+        let rotated = fs::read_to_string(dir.join("alerts.jsonl.1")).unwrap();
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains("third"));
+        assert!(rotated.contains("second") && !rotated.contains("first"));
+        assert!(!dir.join("alerts.jsonl.2").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
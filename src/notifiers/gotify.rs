@@ -0,0 +1,136 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use log::{info,debug};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::notifiers::render::md_escape;
+use crate::notifiers::{http_client, DetailKind, Format, NotifyEvent, Severity, Sink};
+
+/// Gotify server used by the `gotify:` sinks (top-level `gotify` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct GotifySettings {
+    pub server: String,
+    /// Application name -> application token
+    pub apps: BTreeMap<String, String>,
+}
+
+pub struct GotifySink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    app: String,
+    token: String,
+}
+
+impl std::fmt::Debug for GotifySink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GotifySink(app={})", self.app)
+    }
+}
+
+impl GotifySink {
+    pub fn new(settings: &GotifySettings, app: &str) -> Result<Self> {
+        let Some(token) = settings.apps.get(app) else {
+            anyhow::bail!("Gotify app '{app}' not defined in 'gotify.apps'");
+        };
+        let url = reqwest::Url::parse(&format!("{}/message", settings.server.trim_end_matches('/')))
+            .with_context(|| format!("Invalid Gotify server '{}'", settings.server))?;
+        Ok(Self { client: http_client()?, url, app: app.to_string(), token: token.clone() })
+    }
+}
+
+/// Gotify priorities go from 0 to 10 (8+ rings on Android).
+fn priority(severity: Severity) -> u8 {
+    match severity {
+        Severity::Low => 2,
+        Severity::Normal => 5,
+        Severity::High => 8,
+        Severity::Critical => 10,
+    }
+}
+
+#[async_trait]
+impl Sink for GotifySink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from gotify..");
+        let mut body = json!({
+            "title": ev.headline(),
+            "message": ev.render(Format::Markdown),
+            "priority": priority(ev.severity),
+            "extras": { "client::display": { "contentType": "text/markdown" } },
+        });
+        if let Some(link) = ev.details.iter().find(|d| d.kind == DetailKind::Link) {
+            body["extras"]["client::notification"] = json!({ "click": { "url": link.value } });
+        }
+        self.client.post(self.url.clone())
+            .header("X-Gotify-Key", &self.token)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        debug!("Sent by gotify to {}", self.app);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("gotify:{}", self.app)
+    }
+
+    /// Template variables cannot add Markdown markup or links.
+    fn escape(&self, value: &str) -> String {
+        md_escape(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::Detail;
+    use crate::notifiers::testing::http_server;
+
+    fn settings(server: String) -> GotifySettings {
+        GotifySettings { server, apps: BTreeMap::from([("ops".to_string(), "AppTok".to_string())]) }
+    }
+
+    fn event() -> NotifyEvent {
+        NotifyEvent {
+            title: "Disk full".to_string(),
+            severity: Severity::High,
+            details: vec![Detail::text("Host", "db1"), Detail::link("Report", "https://example.org/r/1")],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn posts_message_with_key_priority_and_click() {
+        let (url, requests) = http_server(vec![]);
+        let sink = GotifySink::new(&settings(format!("{url}/")), "ops").unwrap();
+        sink.send(&event()).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let req = &requests[0];
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("POST", "/message"));
+        assert_eq!(req.header("x-gotify-key"), Some("AppTok"));
+        let body = req.json();
+        assert_eq!(body["title"], "Disk full");
+        assert_eq!(body["priority"], 8);
+        assert_eq!(body["extras"]["client::display"]["contentType"], "text/markdown");
+        assert_eq!(body["extras"]["client::notification"]["click"]["url"], "https://example.org/r/1");
+        assert!(body["message"].as_str().unwrap().contains("db1"));
+    }
+
+    #[tokio::test]
+    async fn error_status_is_an_error() {
+        let (url, _) = http_server(vec![401]);
+        let sink = GotifySink::new(&settings(url), "ops").unwrap();
+        let err = sink.send(&event()).await.unwrap_err();
+        assert!(format!("{err:#}").contains("401"), "{err:#}");
+    }
+
+    #[test]
+    fn unknown_app_is_refused() {
+        assert!(GotifySink::new(&settings("http://localhost".to_string()), "dev").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{info,debug};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::notifiers::{http_client, Format, NotifyEvent, Sink};
+
+/// Matrix account used by the `matrix:` sinks (top-level `matrix` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixSettings {
+    /// e.g. `https://matrix.example.com`
+    pub homeserver: String,
+    pub access_token: String,
+    /// Send `m.notice` (no bot loops, often not highlighted) instead of `m.text`
+    #[serde(default)]
+    pub notice: bool,
+}
+
+pub struct MatrixSink {
+    client: reqwest::Client,
+    settings: MatrixSettings,
+    room: String,
+    /// Makes transaction IDs unique across restarts
+    session: u128,
+}
+
+impl std::fmt::Debug for MatrixSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MatrixSink(room={})", self.room)
+    }
+}
+
+impl MatrixSink {
+    pub fn new(settings: &MatrixSettings, room: &str) -> Result<Self> {
+        if !room.starts_with('!') || !room.contains(':') {
+            anyhow::bail!("Invalid Matrix room ID '{room}', expected '!id:server'");
+        }
+        reqwest::Url::parse(&settings.homeserver)
+            .with_context(|| format!("Invalid Matrix homeserver '{}'", settings.homeserver))?;
+        let session = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        Ok(Self { client: http_client()?, settings: settings.clone(), room: room.to_string(), session })
+    }
+
+    /// Client-server API URL, `segments` are percent-encoded.
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&self.settings.homeserver)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Matrix homeserver '{}'", self.settings.homeserver))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Sink for MatrixSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from matrix..");
+        // Same event = same transaction ID, so a retry after a lost answer is not posted twice
+        let txn = format!("dende-{}-{}", self.session, ev.id);
+        let url = self.url(&["rooms", &self.room, "send", "m.room.message", &txn])?;
+        let body = json!({
+            "msgtype": if self.settings.notice { "m.notice" } else { "m.text" },
+            "body": ev.render(Format::Plain),
+            "format": "org.matrix.custom.html",
+            "formatted_body": ev.render(Format::Html),
+        });
+        self.client.put(url)
+            .bearer_auth(&self.settings.access_token)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        debug!("Sent by matrix to {}", self.room);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("matrix:{}", self.room)
+    }
+
+    /// Check the access token (whoami).
+    async fn health_check(&self) -> Result<()> {
+        let res: serde_json::Value = self.client.get(self.url(&["account", "whoami"])?)
+            .bearer_auth(&self.settings.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        debug!("Matrix running as {} for one job!", res["user_id"].as_str().unwrap_or("unknown"));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::testing::http_server;
+
+    #[tokio::test]
+    async fn retry_reuses_the_transaction_id() {
+        let (url, requests) = http_server(vec![500]);
+        let settings = MatrixSettings { homeserver: format!("{url}/"), access_token: "tok".to_string(), notice: true };
+        let sink = MatrixSink::new(&settings, "!room:example.org").unwrap();
+        let first = NotifyEvent { id: 1, msg: "disk <full>".to_string(), ..Default::default() };
+        assert!(sink.send(&first).await.is_err());
+        sink.send(&first).await.unwrap();
+        sink.send(&NotifyEvent { id: 2, ..first.clone() }).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+        assert!(requests.iter().all(|r| r.method == "PUT" && r.header("authorization") == Some("Bearer tok")));
+        assert!(paths[0].starts_with("/_matrix/client/v3/rooms/!room:example.org/send/m.room.message/dende-"), "{}", paths[0]);
+        assert_eq!(paths[0], paths[1]);
+        assert_ne!(paths[1], paths[2]);
+
+        let body = requests[0].json();
+        assert_eq!(body["msgtype"], "m.notice");
+        assert_eq!(body["body"], "disk <full>");
+        assert_eq!(body["formatted_body"], first.render(Format::Html));
+    }
+
+    #[test]
+    fn invalid_room_is_refused() {
+        let settings = MatrixSettings { homeserver: "https://m.example.org".to_string(), access_token: String::new(), notice: false };
+        assert!(MatrixSink::new(&settings, "#alias:example.org").is_err());
+        assert!(MatrixSink::new(&settings, "!nocolon").is_err());
+    }
+}
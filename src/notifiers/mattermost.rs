@@ -0,0 +1,126 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::{info,debug};
+use serde_json::{json, Value};
+
+use crate::notifiers::render::{clip, code_block, md_escape};
+use crate::notifiers::slack::IncomingWebhook;
+use crate::notifiers::{http_client, DetailKind, NotifyEvent, Sink};
+
+#[derive(Debug)]
+pub struct MattermostSink {
+    client: reqwest::Client,
+    name: String,
+    hook: IncomingWebhook,
+}
+
+impl MattermostSink {
+    pub fn new(name: &str, hook: &IncomingWebhook) -> Result<Self> {
+        Ok(Self { client: http_client()?, name: name.to_string(), hook: hook.clone() })
+    }
+}
+
+/// Message attachment: title, short fields, code blocks, then links.
+fn attachment(ev: &NotifyEvent) -> Value {
+    if let Some(text) = &ev.text {
+        return json!({
+            "fallback": ev.headline(),
+            "title": ev.headline(),
+            "text": clip(text, 8000),
+        });
+    }
+    if ev.details.is_empty() {
+        return json!({
+            "fallback": ev.headline(),
+            "title": ev.headline(),
+            "text": code_block(&ev.msg, 8000),
+        });
+    }
+
+    let mut fields: Vec<Value> = ev.job.iter()
+        .map(|j| json!({ "short": true, "title": "Job", "value": j }))
+        .collect();
+    for d in &ev.details {
+        let value = match d.kind {
+            DetailKind::Text => d.value.clone(),
+            DetailKind::Code => code_block(&d.value, 4000),
+            DetailKind::Link => format!("[{}]({})", d.value, d.value),
+        };
+        fields.push(json!({ "short": d.kind == DetailKind::Text, "title": d.label, "value": value }));
+    }
+    let mut attachment = json!({
+        "fallback": ev.headline(),
+        "color": "#d00000",
+        "title": ev.headline(),
+        "fields": fields,
+    });
+    if let Some(link) = ev.details.iter().find(|d| d.kind == DetailKind::Link) {
+        attachment["title_link"] = json!(link.value);
+    }
+    attachment
+}
+
+#[async_trait]
+impl Sink for MattermostSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from mattermost..");
+        let payload = json!({ "attachments": [attachment(ev)] });
+        self.hook.post(&self.client, payload).await?;
+        debug!("Sent by mattermost to {}", self.name);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("mattermost:{}", self.name)
+    }
+
+    /// Template variables cannot add markup, links or mentions (`@channel`).
+    fn escape(&self, value: &str) -> String {
+        md_escape(value).replace('@', "@\u{200b}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::Detail;
+
+    #[test]
+    fn attachment_of_a_detailed_alert() {
+        let ev = NotifyEvent {
+            title: "matched".to_string(),
+            job: Some("auth".to_string()),
+            details: vec![
+                Detail::text("User", "root"),
+                Detail::code("Lines", format!("```\n{}", "x".repeat(5000))),
+                Detail::link("Report", "https://example.org/r"),
+            ],
+            ..Default::default()
+        };
+        let a = attachment(&ev);
+        assert_eq!(a["title"], "matched");
+        assert_eq!(a["title_link"], "https://example.org/r");
+        let fields = a["fields"].as_array().unwrap();
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields[0], json!({ "short": true, "title": "Job", "value": "auth" }));
+        assert_eq!(fields[1]["short"], true);
+        let code = fields[2]["value"].as_str().unwrap();
+        assert!(code.starts_with("```\n`\u{200b}``\n") && code.ends_with("…\n```"), "{code}");
+        assert!(code.chars().count() <= 4000);
+        assert_eq!(fields[3]["value"], "[https://example.org/r](https://example.org/r)");
+    }
+
+    #[test]
+    fn attachment_of_a_templated_alert() {
+        let ev = NotifyEvent { title: "t".to_string(), text: Some("**hi**".to_string()), ..Default::default() };
+        let a = attachment(&ev);
+        assert_eq!(a["text"], "**hi**");
+        assert!(a.get("fields").is_none());
+    }
+
+    #[test]
+    fn template_variables_cannot_mention_or_link() {
+        let sink = MattermostSink::new("ops", &IncomingWebhook { url: String::new(), channel: None, username: None }).unwrap();
+        assert_eq!(sink.escape("@channel [x](https://evil)"), "@\u{200b}channel \\[x\\](https://evil)");
+    }
+}
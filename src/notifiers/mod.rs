@@ -1,142 +1,545 @@
-use anyhow::Result;
+use anyhow::{Result, Context};
+use async_trait::async_trait;
 use tokio::task::JoinHandle;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 pub mod telegram;
 pub mod console;
+pub mod email;
+pub mod sms;
+pub mod webhook;
+pub mod slack;
+pub mod mattermost;
+pub mod discord;
+pub mod matrix;
+pub mod ntfy;
+pub mod gotify;
+pub mod file;
+pub mod exec;
+pub mod batch;
+pub mod render;
+pub mod template;
+#[cfg(test)]
+pub(crate) mod testing;
 // pub mod newnotifier;
 
 use console::ConsoleSink;
+use email::{EmailSink, SmtpSettings};
+use sms::{SmsSettings, SmsSink};
+use webhook::{WebhookSink, WebhookSpec};
+use slack::{IncomingWebhook, SlackSink};
+use mattermost::MattermostSink;
+use discord::{DiscordSink, DiscordWebhook};
+use matrix::{MatrixSettings, MatrixSink};
+use ntfy::{NtfySettings, NtfySink};
+use gotify::{GotifySettings, GotifySink};
+use file::{FileSink, FileSinkSettings};
+use exec::{ExecSink, ExecSpec};
 use telegram::TelegramSink;
+pub use render::Format;
+use batch::{Batch, BatchSpec};
+use template::Template;
 use log::{trace,error};
 
+use crate::args::GlobalSettings;
+
 /// Aggregates all selected sinks and dispatches notifications to them.
 pub struct Notifier {
     tx: tokio::sync::mpsc::UnboundedSender<NotifyEvent>,
     #[allow(dead_code)]
     task: JoinHandle<()>,
+    /// Stamped on the events that do not name their job
+    job: Option<String>,
+    /// Closed when the dispatch task ends (buffered digests sent)
+    done: tokio::sync::watch::Receiver<()>,
 }
 
-#[derive(Clone, Debug)]
+/// A sink with what the notifier applies before sending to it.
+struct Route {
+    sink: Box<dyn Sink>,
+    template: Option<Template>,
+    /// Alerts are buffered and sent as digests
+    batch: Option<Batch>,
+}
+
+/// Next `NotifyEvent::id`, shared by queued alerts and digests.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What a sink already delivered of the last alert it sent in several parts, so
+/// a retry of that alert resumes after them instead of sending them twice.
+#[derive(Debug, Default)]
+pub(crate) struct Delivered<T>(Mutex<(u64, T)>);
+
+impl<T: Copy + Default> Delivered<T> {
+    /// Progress of alert `id`, the default one if it is not the last alert.
+    pub fn get(&self, id: u64) -> T {
+        match *self.0.lock().unwrap_or_else(|e| e.into_inner()) {
+            (last, progress) if last == id => progress,
+            _ => T::default(),
+        }
+    }
+
+    pub fn set(&self, id: u64, progress: T) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = (id, progress);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct NotifyEvent {
+    /// Unique in the process, set when queued (e.g. for idempotent retries).
+    pub id: u64,
+    /// Free text of the alerts without details (`Notifier::notify`), see `render` otherwise.
     pub msg: String,
+    /// Message written by a `template`, sent as is (markup of the destination included).
+    pub text: Option<String>,
+    /// The same with its variables HTML-escaped, for the HTML formats.
+    pub html_text: Option<String>,
+    /// Named values attached to the alert (e.g. regex captures like `user`, `ip`).
+    pub fields: BTreeMap<String, String>,
+    /// Job that raised the alert.
+    pub job: Option<String>,
+    /// Module that raised the alert, e.g. `log-watcher` or `virustotal-watcher`.
+    pub source: String,
+    pub severity: Severity,
+    /// Headline of the alert, e.g. `!dende-rs::log-watcher::matched!`.
+    pub title: String,
+    /// Labelled parts of the alert, for sinks with a rich layout (Slack, ...).
+    pub details: Vec<Detail>,
+    /// Machine-readable data of the alert (file, line number, VT fields, ...), a JSON object.
+    pub payload: serde_json::Value,
+}
+
+/// How urgent an alert is (mapped to push priorities, colours, ...).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    #[default]
+    Normal,
+    /// Log matches
+    High,
+    /// Payload published on VirusTotal
+    Critical,
 }
 
-/// Concrete sink types we support. Add new variants as you add files.
-enum Sink {
-    Console(ConsoleSink),
-    Telegram(TelegramSink),
-    // NewNotifier(NewNotifierSink),
+/// How a detail of an alert should be shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DetailKind {
+    Text,
+    /// Verbatim content (log lines), shown in a code block
+    Code,
+    /// URL, shown as a link or a button
+    Link,
 }
 
-impl Sink {
-    async fn send(&self, _msg: &str) -> Result<()> {
-        match self {
-            Sink::Console(s) => s.send(_msg).await,
-            Sink::Telegram(s) => s.send(_msg).await,
-            // // Easy to add another notifier here
-            // Sink::NewNotifier(s) => s.send(_text).await,
+/// One labelled part of an alert (file and line, matched content, VT link, ...).
+#[derive(Clone, Debug)]
+pub struct Detail {
+    pub label: String,
+    pub value: String,
+    pub kind: DetailKind,
+}
+
+impl Detail {
+    pub fn text(label: &str, value: impl Into<String>) -> Self {
+        Self { label: label.to_string(), value: value.into(), kind: DetailKind::Text }
+    }
+
+    pub fn code(label: &str, value: impl Into<String>) -> Self {
+        Self { label: label.to_string(), value: value.into(), kind: DetailKind::Code }
+    }
+
+    pub fn link(label: &str, url: impl Into<String>) -> Self {
+        Self { label: label.to_string(), value: url.into(), kind: DetailKind::Link }
+    }
+}
+
+impl NotifyEvent {
+    /// The alert as one JSON object: common fields, then the payload of the source module.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut obj = serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "id": self.id,
+            "job": self.job,
+            "source": self.source,
+            "severity": self.severity,
+            "title": self.headline(),
+        });
+        match &self.payload {
+            serde_json::Value::Object(payload) => {
+                for (k, v) in payload {
+                    obj[k] = v.clone();
+                }
+            }
+            // No structured data: keep the human-formatted message
+            _ => obj["message"] = serde_json::json!(self.msg),
+        }
+        if let Some(text) = &self.text {
+            obj["message"] = serde_json::json!(text);
+        }
+        if !self.fields.is_empty() && obj.get("captures").is_none() {
+            obj["fields"] = serde_json::json!(self.fields);
+        }
+        obj
+    }
+
+    /// The title, or the first line of the message when there is none.
+    pub fn headline(&self) -> &str {
+        if !self.title.is_empty() {
+            return &self.title;
         }
+        self.msg.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or_default()
     }
 }
 
-impl Notifier {
-    pub fn new(
-        to_raw: Vec<String>,
-        telegram_token: Option<String>
-    ) -> Result<Self> {
+/// A notification destination (console, Telegram, ...).
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Deliver one notification. An error makes the notifier retry.
+    async fn send(&self, ev: &NotifyEvent) -> Result<()>;
 
-        let mut sinks: Vec<Sink> = Vec::new();
+    /// Short description used in logs, e.g. `tg:12345`.
+    fn name(&self) -> String;
 
-        for to in to_raw {
-            let to = to.trim();
-
-            match to.split_once(':') {
-                
-                // Telegram notifier
-                Some(("tg", id)) => {
-                    match id.parse::<i64>() {
-                        Ok(id) => {
-                            if let Some(token) = telegram_token.clone() {
-                                sinks.push(Sink::Telegram(TelegramSink::new(token, id)));
-                            } else {
-                                error!("Skipping Telegram dest {id}: no token provided");
-                                continue;
-                            }
-                        }
-                        Err(e) => {
-                            error!("Invalid Telegram UserId({id}): {e}");
-                            continue;
-                        },
-                    }
-                }
+    /// Check the destination is reachable (credentials, server). Called once at startup.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Escape a template variable for the markup of `NotifyEvent::text` at this
+    /// destination (mrkdwn, Markdown, ...). Plain text sinks keep it as is.
+    fn escape(&self, value: &str) -> String {
+        value.to_string()
+    }
+}
 
-                // Email notifier
-                Some(("email", _addr)) => {
-                    error!("Email notification has not been yet implemented.");
-                    continue;
-                },
+/// Settings a sink may need besides its `to:` target.
+#[derive(Clone, Debug, Default)]
+pub struct SinkContext {
+    /// Job the notifier belongs to
+    pub job: Option<String>,
+    pub telegram_token: Option<String>,
+    pub smtp: Option<SmtpSettings>,
+    pub sms: Option<SmsSettings>,
+    pub webhooks: BTreeMap<String, WebhookSpec>,
+    pub slack: BTreeMap<String, IncomingWebhook>,
+    pub mattermost: BTreeMap<String, IncomingWebhook>,
+    pub discord: BTreeMap<String, DiscordWebhook>,
+    pub matrix: Option<MatrixSettings>,
+    pub ntfy: Option<NtfySettings>,
+    pub gotify: Option<GotifySettings>,
+    pub file: FileSinkSettings,
+    pub exec: BTreeMap<String, ExecSpec>,
+    /// Message template of the job (or the global one)
+    pub template: Option<Template>,
+    /// Per-destination templates, keyed by `to` entry (`tg:123`) or scheme (`tg`)
+    pub templates: BTreeMap<String, Template>,
+    /// Digest settings of the job
+    pub batch: Option<BatchSpec>,
+    /// Per-destination digest settings, keyed like `templates`
+    pub batches: BTreeMap<String, BatchSpec>,
+}
 
-                // SMS notifier
-                Some(("sms", _num)) => {
-                    error!("SMS notification has not been yet implemented.");
-                    continue;
-                }
+impl From<&GlobalSettings> for SinkContext {
+    fn from(globals: &GlobalSettings) -> Self {
+        Self {
+            job: None,
+            telegram_token: globals.telegram_token.clone(),
+            smtp: globals.smtp.clone(),
+            sms: globals.sms.clone(),
+            webhooks: globals.webhooks.clone(),
+            slack: globals.slack.clone(),
+            mattermost: globals.mattermost.clone(),
+            discord: globals.discord.clone(),
+            matrix: globals.matrix.clone(),
+            ntfy: globals.ntfy.clone(),
+            gotify: globals.gotify.clone(),
+            file: globals.file.clone(),
+            exec: globals.exec.clone(),
+            template: None,
+            templates: BTreeMap::new(),
+            batch: None,
+            batches: BTreeMap::new(),
+        }
+    }
+}
 
-                // Console notifier
-                Some(("console", tag)) => {
-                    sinks.push(Sink::Console(ConsoleSink::new(tag.to_string())));
-                    continue;
-                }
+/// Builds a sink from the part of a `to:` entry after the scheme (`12345` in `tg:12345`).
+pub type SinkFactory = Arc<dyn Fn(&str, &SinkContext) -> Result<Box<dyn Sink>> + Send + Sync>;
 
-                // Unknown notifier
-                Some((_scheme, _rest)) => {
-                    error!("{_scheme} unknown!");
-                    continue;
-                }
+/// Maps `to:` schemes (`tg`, `console`, ...) to sink factories.
+#[derive(Clone)]
+pub struct SinkRegistry {
+    factories: HashMap<String, SinkFactory>,
+}
 
-                None => {
-                    continue;
+impl Default for SinkRegistry {
+    /// Registry with the built-in sinks.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("console", |tag, _| Ok(Box::new(ConsoleSink::new(tag.to_string()))));
+        registry.register("tg", |id, ctx| {
+            let id = id.parse::<i64>()
+                .map_err(|e| anyhow::anyhow!("Invalid Telegram UserId({id}): {e}"))?;
+            let Some(token) = ctx.telegram_token.clone() else {
+                anyhow::bail!("Skipping Telegram dest {id}: no token provided");
+            };
+            Ok(Box::new(TelegramSink::new(token, id)))
+        });
+        registry.register("email", |addr, ctx| {
+            let Some(smtp) = ctx.smtp.as_ref() else {
+                anyhow::bail!("Skipping email dest {addr}: no 'smtp' settings provided");
+            };
+            Ok(Box::new(EmailSink::new(smtp, addr)?))
+        });
+        registry.register("sms", |number, ctx| {
+            let Some(sms) = ctx.sms.as_ref() else {
+                anyhow::bail!("Skipping SMS dest {number}: no 'sms' settings provided");
+            };
+            Ok(Box::new(SmsSink::new(sms, number)?))
+        });
+        registry.register("webhook", |name, ctx| {
+            let Some(spec) = ctx.webhooks.get(name) else {
+                anyhow::bail!("Skipping webhook dest {name}: not defined in 'webhooks'");
+            };
+            Ok(Box::new(WebhookSink::new(name, spec)?))
+        });
+        registry.register("slack", |name, ctx| {
+            let Some(hook) = ctx.slack.get(name) else {
+                anyhow::bail!("Skipping Slack dest {name}: not defined in 'slack'");
+            };
+            Ok(Box::new(SlackSink::new(name, hook)?))
+        });
+        registry.register("mattermost", |name, ctx| {
+            let Some(hook) = ctx.mattermost.get(name) else {
+                anyhow::bail!("Skipping Mattermost dest {name}: not defined in 'mattermost'");
+            };
+            Ok(Box::new(MattermostSink::new(name, hook)?))
+        });
+        registry.register("discord", |name, ctx| {
+            let Some(hook) = ctx.discord.get(name) else {
+                anyhow::bail!("Skipping Discord dest {name}: not defined in 'discord'");
+            };
+            Ok(Box::new(DiscordSink::new(name, hook)?))
+        });
+        registry.register("matrix", |room, ctx| {
+            let Some(matrix) = ctx.matrix.as_ref() else {
+                anyhow::bail!("Skipping Matrix dest {room}: no 'matrix' settings provided");
+            };
+            Ok(Box::new(MatrixSink::new(matrix, room)?))
+        });
+        registry.register("ntfy", |topic, ctx| {
+            let Some(ntfy) = ctx.ntfy.as_ref() else {
+                anyhow::bail!("Skipping ntfy dest {topic}: no 'ntfy' settings provided");
+            };
+            Ok(Box::new(NtfySink::new(ntfy, topic)?))
+        });
+        registry.register("gotify", |app, ctx| {
+            let Some(gotify) = ctx.gotify.as_ref() else {
+                anyhow::bail!("Skipping Gotify dest {app}: no 'gotify' settings provided");
+            };
+            Ok(Box::new(GotifySink::new(gotify, app)?))
+        });
+        registry.register("file", |path, ctx| Ok(Box::new(FileSink::new(&ctx.file, path)?)));
+        registry.register("exec", |name, ctx| {
+            let Some(spec) = ctx.exec.get(name) else {
+                anyhow::bail!("Skipping exec dest {name}: not defined in 'exec'");
+            };
+            Ok(Box::new(ExecSink::new(name, spec)?))
+        });
+        registry
+    }
+}
+
+impl SinkRegistry {
+    /// Registry without any sink.
+    pub fn empty() -> Self {
+        Self { factories: HashMap::new() }
+    }
+
+    /// Add (or replace) the factory of a scheme.
+    pub fn register<F>(&mut self, scheme: &str, factory: F)
+    where
+        F: Fn(&str, &SinkContext) -> Result<Box<dyn Sink>> + Send + Sync + 'static,
+    {
+        self.factories.insert(scheme.to_string(), Arc::new(factory));
+    }
+
+    /// Build the sink of one `to:` entry (`scheme:target`, plain text = console tag).
+    pub fn build(&self, to: &str, ctx: &SinkContext) -> Result<Box<dyn Sink>> {
+        let (scheme, target) = split_to(to);
+        match self.factories.get(scheme) {
+            Some(factory) => factory(target, ctx),
+            None => anyhow::bail!("{scheme} unknown!"),
+        }
+    }
+}
+
+/// `scheme:target` of a `to:` entry; plain text is a console tag.
+pub(crate) fn split_to(to: &str) -> (&str, &str) {
+    let to = to.trim();
+    to.split_once(':').unwrap_or(("console", to))
+}
+
+/// HTTP client shared by the sinks calling web APIs.
+pub(crate) fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(15))
+        .build()
+        .context("Building HTTP client")
+}
+
+impl Notifier {
+    /// Build a notifier from the built-in sinks.
+    pub fn new(to_raw: Vec<String>, ctx: &SinkContext) -> Result<Self> {
+        Self::with_registry(&SinkRegistry::default(), to_raw, ctx)
+    }
+
+    /// Build a notifier from the sinks of `registry`. Destinations that cannot be
+    /// built are logged and skipped, invalid batch settings are an error.
+    pub fn with_registry(
+        registry: &SinkRegistry,
+        to_raw: Vec<String>,
+        ctx: &SinkContext,
+    ) -> Result<Self> {
+
+        let mut routes = Vec::new();
+
+        for to in to_raw {
+            match registry.build(&to, ctx) {
+                Ok(sink) => {
+                    let (scheme, _) = split_to(&to);
+                    let template = ctx.templates.get(to.trim())
+                        .or_else(|| ctx.templates.get(scheme))
+                        .or(ctx.template.as_ref())
+                        .cloned();
+                    let batch = ctx.batches.get(to.trim())
+                        .or_else(|| ctx.batches.get(scheme))
+                        .or(ctx.batch.as_ref())
+                        .map(Batch::new)
+                        .transpose()
+                        .with_context(|| format!("Destination '{to}'"))?;
+                    routes.push(Route { sink, template, batch });
                 }
+                Err(e) => error!("{e}"),
             }
-
         }
 
+        let mut notifier = Self::spawn(routes);
+        notifier.job = ctx.job.clone();
+        Ok(notifier)
+    }
+
+    /// Build a notifier dispatching to already built sinks.
+    pub fn from_sinks(sinks: Vec<Box<dyn Sink>>) -> Self {
+        Self::spawn(sinks.into_iter().map(|sink| Route { sink, template: None, batch: None }).collect())
+    }
+
+    /// Start the dispatch task; a sink with a template gets the alert text written by
+    /// it, a sink with a batch gets digests once they are due (and on shutdown).
+    fn spawn(mut routes: Vec<Route>) -> Self {
+
         let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<NotifyEvent>();
+        let (done_tx, done) = tokio::sync::watch::channel(());
 
         let task = tokio::spawn(async move {
-            while let Some(ev) = rx.recv().await {
-                for sink in &sinks {
-                    let mut delay = Duration::from_millis(400);
-                    for attempt in 1..=3 {
-                        let res = sink.send(&ev.msg).await;
-                        match res {
-                            Ok(_) => {
-                                trace!("notifier sink well work, notification sent!");
-                                break;
-                            }
-                            Err(e) => {
-                                if attempt == 3 {
-                                    error!("send failed after {attempt} attempts: {e}");
-                                    break;
+            for route in &routes {
+                if let Err(e) = route.sink.health_check().await {
+                    error!("{} health check failed: {e}", route.sink.name());
+                }
+            }
+            loop {
+                let deadline = routes.iter().filter_map(|r| r.batch.as_ref()?.deadline()).min();
+                let ev = tokio::select! {
+                    ev = rx.recv() => match ev {
+                        Some(ev) => Some(ev),
+                        None => break,
+                    },
+                    _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => None,
+                };
+                for route in &mut routes {
+                    if let Some(ev) = &ev {
+                        let ev = match &route.template {
+                            Some(t) => {
+                                let vars = template::variables(ev);
+                                NotifyEvent {
+                                    text: Some(t.render(&vars, |v| route.sink.escape(v))),
+                                    html_text: Some(t.render_html(&vars)),
+                                    ..ev.clone()
                                 }
-                                error!("notifier sink error, send failed (attempt {attempt}/3): {e} - retry in {} ms",delay.as_millis());
-                                tokio::time::sleep(delay).await;
-                                delay = std::cmp::min(delay * 2, Duration::from_secs(5));
                             }
+                            None => ev.clone(),
+                        };
+                        match &mut route.batch {
+                            Some(batch) => batch.push(ev),
+                            None => deliver(route.sink.as_ref(), &ev).await,
                         }
                     }
+                    if let Some(mut digest) = route.batch.as_mut().and_then(Batch::due) {
+                        digest.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+                        deliver(route.sink.as_ref(), &digest).await;
+                    }
+                }
+            }
+            // Every sender is gone (shutdown): send what is still buffered
+            for route in &mut routes {
+                if let Some(mut digest) = route.batch.as_mut().and_then(Batch::take) {
+                    digest.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+                    deliver(route.sink.as_ref(), &digest).await;
                 }
             }
+            drop(done_tx);
         });
 
-        Ok(Self { tx, task })
+        Self { tx, task, job: None, done }
+    }
+
+    /// Resolves once the notifier is dropped and its buffered digests are sent.
+    pub fn drained(&self) -> impl Future<Output = ()> + Send + use<> {
+        let mut done = self.done.clone();
+        async move { while done.changed().await.is_ok() {} }
     }
 
     /// Queue a notification event for processing by the async task.
     pub fn notify(&self, msg: &str) {
-        let _ = self.tx.send(NotifyEvent {
+        self.notify_event(NotifyEvent {
             msg: msg.to_string(),
+            ..Default::default()
         });
     }
-}
\ No newline at end of file
+
+    /// Queue a notification event carrying named fields.
+    pub fn notify_event(&self, mut ev: NotifyEvent) {
+        ev.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        if ev.job.is_none() {
+            ev.job = self.job.clone();
+        }
+        let _ = self.tx.send(ev);
+    }
+}
+
+/// Send one alert to a sink, retrying failed attempts.
+async fn deliver(sink: &dyn Sink, ev: &NotifyEvent) {
+    let mut delay = Duration::from_millis(400);
+    for attempt in 1..=3 {
+        let res = sink.send(ev).await;
+        match res {
+            Ok(_) => {
+                trace!("notifier sink {} well work, notification sent!", sink.name());
+                break;
+            }
+            Err(e) => {
+                if attempt == 3 {
+                    error!("{} send failed after {attempt} attempts: {e}", sink.name());
+                    break;
+                }
+                error!("notifier sink {} error, send failed (attempt {attempt}/3): {e} - retry in {} ms", sink.name(), delay.as_millis());
+                tokio::time::sleep(delay).await;
+                delay = std::cmp::min(delay * 2, Duration::from_secs(5));
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use log::{info,debug};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::notifiers::render::md_escape;
+use crate::notifiers::{http_client, DetailKind, Format, NotifyEvent, Severity, Sink};
+
+/// ntfy server used by the `ntfy:` sinks (top-level `ntfy` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct NtfySettings {
+    #[serde(default = "default_server")]
+    pub server: String,
+    /// Access token of a protected server
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Tags (emoji shortcodes) added to every message; the source module is always added
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_server() -> String { "https://ntfy.sh".to_string() }
+
+pub struct NtfySink {
+    client: reqwest::Client,
+    settings: NtfySettings,
+    topic: String,
+}
+
+impl std::fmt::Debug for NtfySink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NtfySink(topic={})", self.topic)
+    }
+}
+
+impl NtfySink {
+    pub fn new(settings: &NtfySettings, topic: &str) -> Result<Self> {
+        if topic.is_empty() || !topic.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            anyhow::bail!("Invalid ntfy topic '{topic}'");
+        }
+        reqwest::Url::parse(&settings.server)
+            .with_context(|| format!("Invalid ntfy server '{}'", settings.server))?;
+        Ok(Self { client: http_client()?, settings: settings.clone(), topic: topic.to_string() })
+    }
+}
+
+/// ntfy priorities go from 1 (min) to 5 (max).
+fn priority(severity: Severity) -> u8 {
+    match severity {
+        Severity::Low => 2,
+        Severity::Normal => 3,
+        Severity::High => 4,
+        Severity::Critical => 5,
+    }
+}
+
+#[async_trait]
+impl Sink for NtfySink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from ntfy..");
+        let mut tags = self.settings.tags.clone();
+        if !ev.source.is_empty() {
+            tags.push(ev.source.clone());
+        }
+        // JSON publishing: the title may hold non-ASCII characters, unlike headers
+        let mut body = json!({
+            "topic": self.topic,
+            "title": ev.headline(),
+            "message": ev.render(Format::Markdown),
+            "markdown": true,
+            "priority": priority(ev.severity),
+            "tags": tags,
+        });
+        if let Some(link) = ev.details.iter().find(|d| d.kind == DetailKind::Link) {
+            body["click"] = json!(link.value);
+        }
+        let mut req = self.client.post(self.settings.server.trim_end_matches('/')).json(&body);
+        if let Some(token) = &self.settings.token {
+            req = req.bearer_auth(token);
+        }
+        req.send().await?.error_for_status()?;
+        debug!("Sent by ntfy to {}", self.topic);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("ntfy:{}", self.topic)
+    }
+
+    /// Template variables cannot add Markdown markup or links.
+    fn escape(&self, value: &str) -> String {
+        md_escape(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::Detail;
+    use crate::notifiers::testing::http_server;
+
+    fn settings(server: String, token: Option<&str>) -> NtfySettings {
+        NtfySettings { server, token: token.map(str::to_string), tags: vec!["warning".to_string()] }
+    }
+
+    fn event() -> NotifyEvent {
+        NotifyEvent {
+            title: "Disk full".to_string(),
+            source: "log-watcher".to_string(),
+            severity: Severity::Critical,
+            details: vec![Detail::text("Host", "db1"), Detail::link("Report", "https://example.org/r/1")],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_json_with_priority_tags_and_click() {
+        let (url, requests) = http_server(vec![]);
+        let sink = NtfySink::new(&settings(url, Some("tk_secret")), "alerts").unwrap();
+        sink.send(&event()).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let req = &requests[0];
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("POST", "/"));
+        assert_eq!(req.header("authorization"), Some("Bearer tk_secret"));
+        let body = req.json();
+        assert_eq!(body["topic"], "alerts");
+        assert_eq!(body["title"], "Disk full");
+        assert_eq!(body["priority"], 5);
+        assert_eq!(body["tags"], json!(["warning", "log-watcher"]));
+        assert_eq!(body["click"], "https://example.org/r/1");
+        assert_eq!(body["markdown"], true);
+        assert!(body["message"].as_str().unwrap().contains("db1"));
+    }
+
+    #[tokio::test]
+    async fn no_token_no_authorization() {
+        let (url, requests) = http_server(vec![]);
+        let sink = NtfySink::new(&settings(url, None), "alerts").unwrap();
+        let mut ev = event();
+        ev.details.pop();
+        ev.severity = Severity::Low;
+        sink.send(&ev).await.unwrap();
+
+        let req = requests.lock().unwrap()[0].clone();
+        assert_eq!(req.header("authorization"), None);
+        assert_eq!(req.json()["priority"], 2);
+        assert!(req.json().get("click").is_none());
+    }
+
+    #[tokio::test]
+    async fn error_status_is_an_error() {
+        let (url, _) = http_server(vec![403]);
+        let sink = NtfySink::new(&settings(url, Some("bad")), "alerts").unwrap();
+        let err = sink.send(&event()).await.unwrap_err();
+        assert!(format!("{err:#}").contains("403"), "{err:#}");
+    }
+
+    #[test]
+    fn invalid_topic_is_refused() {
+        assert!(NtfySink::new(&settings(default_server(), None), "a/b").is_err());
+        assert!(NtfySink::new(&settings(default_server(), None), "").is_err());
+    }
+}
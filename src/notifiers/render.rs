@@ -0,0 +1,161 @@
+use crate::notifiers::{DetailKind, NotifyEvent};
+
+/// How a sink wants an alert written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Console, SMS, email text part, ...
+    Plain,
+    /// Full HTML (email, Matrix `formatted_body`)
+    Html,
+    /// Subset understood by Telegram's `ParseMode::Html` (no `<br>`, no headings)
+    TelegramHtml,
+    /// CommonMark (ntfy, Gotify)
+    Markdown,
+    /// One line of JSON, see `NotifyEvent::to_json`
+    Json,
+}
+
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub(crate) fn md_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Cut `s` to `max` characters, "…" included.
+pub(crate) fn clip(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    s.chars().take(max.saturating_sub(1)).chain(std::iter::once('…')).collect()
+}
+
+/// Markdown code block of at most `max` characters that cannot be closed early by
+/// the content: a zero-width space breaks its fences (longer fences are not
+/// understood by Slack or Discord).
+pub(crate) fn code_block(s: &str, max: usize) -> String {
+    // "```\n" + "\n```" take 8 characters
+    format!("```\n{}\n```", clip(&s.replace("```", "`\u{200b}``"), max.saturating_sub(8)))
+}
+
+impl NotifyEvent {
+    /// The alert written for one kind of destination. Events without details
+    /// (`Notifier::notify`) are rendered from their message, templated ones from
+    /// their text (left as written, markup included, variables escaped for HTML).
+    pub fn render(&self, format: Format) -> String {
+        if let Some(text) = &self.text {
+            let html = self.html_text.as_ref().unwrap_or(text);
+            return match format {
+                Format::Html => html.replace('\n', "<br>\n"),
+                Format::TelegramHtml => html.clone(),
+                Format::Json => self.to_json().to_string(),
+                _ => text.clone(),
+            };
+        }
+        match format {
+            Format::Plain => self.plain(),
+            Format::Html => self.html(),
+            Format::TelegramHtml => self.telegram_html(),
+            Format::Markdown => self.markdown(),
+            Format::Json => self.to_json().to_string(),
+        }
+    }
+
+    fn plain(&self) -> String {
+        if self.details.is_empty() {
+            return self.msg.clone();
+        }
+        let mut out = format!("{}\n\n", self.headline());
+        if let Some(job) = &self.job {
+            out.push_str(&format!("Job: {job}\n"));
+        }
+        for d in &self.details {
+            out.push_str(&match d.kind {
+                DetailKind::Text | DetailKind::Link => format!("{}: {}\n", d.label, d.value),
+                DetailKind::Code => format!("{}:\n\n{}\n\n", d.label, d.value),
+            });
+        }
+        out.trim_end().to_string()
+    }
+
+    fn html(&self) -> String {
+        let mut html = format!("<h4>{}</h4>\n", html_escape(self.headline()));
+        if self.details.is_empty() {
+            html.push_str(&format!("<pre>{}</pre>", html_escape(&self.msg)));
+            return html;
+        }
+        if let Some(job) = &self.job {
+            html.push_str(&format!("<b>Job:</b> {}<br>\n", html_escape(job)));
+        }
+        for d in &self.details {
+            let (label, value) = (html_escape(&d.label), html_escape(&d.value));
+            html.push_str(&match d.kind {
+                DetailKind::Text => format!("<b>{label}:</b> {value}<br>\n"),
+                DetailKind::Code => format!("<b>{label}:</b>\n<pre><code>{value}</code></pre>\n"),
+                DetailKind::Link => format!("<b>{label}:</b> <a href=\"{value}\">{value}</a><br>\n"),
+            });
+        }
+        html
+    }
+
+    fn telegram_html(&self) -> String {
+        if self.details.is_empty() {
+            return html_escape(&self.msg);
+        }
+        let mut html = format!("<b>{}</b>\n\n", html_escape(self.headline()));
+        if let Some(job) = &self.job {
+            html.push_str(&format!("<i>Job:</i> <b>{}</b>\n", html_escape(job)));
+        }
+        for d in &self.details {
+            let (label, value) = (html_escape(&d.label), html_escape(&d.value));
+            html.push_str(&match d.kind {
+                DetailKind::Text => format!("<i>{label}:</i> <b>{value}</b>\n"),
+                DetailKind::Code => format!("<i>{label}:</i>\n<pre>{value}</pre>\n"),
+                DetailKind::Link => format!("<a href=\"{value}\">{label}</a>\n"),
+            });
+        }
+        html.trim_end().to_string()
+    }
+
+    fn markdown(&self) -> String {
+        if self.details.is_empty() {
+            return code_block(&self.msg, usize::MAX);
+        }
+        let mut md = format!("**{}**\n\n", md_escape(self.headline()));
+        if let Some(job) = &self.job {
+            md.push_str(&format!("**Job:** {}  \n", md_escape(job)));
+        }
+        for d in &self.details {
+            let label = md_escape(&d.label);
+            md.push_str(&match d.kind {
+                DetailKind::Text => format!("**{label}:** {}  \n", md_escape(&d.value)),
+                DetailKind::Code => format!("**{label}:**\n{}\n", code_block(&d.value, usize::MAX)),
+                DetailKind::Link => format!("[{label}](<{}>)  \n", d.value.replace('>', "%3E")),
+            });
+        }
+        md.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_block_keeps_its_fences_and_size() {
+        let block = code_block("a```b", 100);
+        assert_eq!(block, "```\na`\u{200b}``b\n```");
+        assert_eq!(block.matches("```").count(), 2);
+        let block = code_block(&"x".repeat(50), 20);
+        assert_eq!(block.chars().count(), 20);
+        assert!(block.ends_with("…\n```"));
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::{info,debug};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::notifiers::render::{clip, code_block};
+use crate::notifiers::{http_client, DetailKind, NotifyEvent, Sink};
+
+/// A named incoming webhook (entry of the top-level `slack` or `mattermost` YAML keys).
+#[derive(Debug, Clone, Deserialize)]
+pub struct IncomingWebhook {
+    pub url: String,
+    /// Override the channel set on the webhook
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// Override the name shown as the author of the message
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+impl IncomingWebhook {
+    /// POST `payload` (plus the channel/username overrides) to the webhook.
+    pub(crate) async fn post(&self, client: &reqwest::Client, mut payload: Value) -> Result<()> {
+        if let Some(channel) = &self.channel {
+            payload["channel"] = json!(channel);
+        }
+        if let Some(username) = &self.username {
+            payload["username"] = json!(username);
+        }
+        client.post(&self.url).json(&payload).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct SlackSink {
+    client: reqwest::Client,
+    name: String,
+    hook: IncomingWebhook,
+}
+
+impl SlackSink {
+    pub fn new(name: &str, hook: &IncomingWebhook) -> Result<Self> {
+        Ok(Self { client: http_client()?, name: name.to_string(), hook: hook.clone() })
+    }
+}
+
+/// Slack mrkdwn only needs `&`, `<` and `>` escaped.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Block Kit message: header, short fields, code blocks, then link buttons.
+fn blocks(ev: &NotifyEvent) -> Vec<Value> {
+    let mut blocks = vec![json!({
+        "type": "header",
+        "text": { "type": "plain_text", "text": clip(ev.headline(), 150) },
+    })];
+    if let Some(text) = &ev.text {
+        blocks.push(json!({ "type": "section", "text": { "type": "mrkdwn", "text": clip(text, 2900) } }));
+        return blocks;
+    }
+    if ev.details.is_empty() {
+        blocks.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": code_block(&escape(&ev.msg), 2900) },
+        }));
+        return blocks;
+    }
+
+    let job = ev.job.iter().map(|j| ("Job", j.as_str()));
+    let texts = ev.details.iter().filter(|d| d.kind == DetailKind::Text).map(|d| (d.label.as_str(), d.value.as_str()));
+    let fields: Vec<Value> = job.chain(texts)
+        .map(|(label, value)| json!({ "type": "mrkdwn", "text": clip(&format!("*{}*\n{}", escape(label), escape(value)), 2000) }))
+        .collect();
+    // A section holds at most 10 fields
+    for chunk in fields.chunks(10) {
+        blocks.push(json!({ "type": "section", "fields": chunk }));
+    }
+
+    for d in ev.details.iter().filter(|d| d.kind == DetailKind::Code) {
+        blocks.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": format!("*{}*\n{}", escape(&d.label), code_block(&escape(&d.value), 2900)) },
+        }));
+    }
+
+    let buttons: Vec<Value> = ev.details.iter()
+        .filter(|d| d.kind == DetailKind::Link)
+        .map(|d| json!({
+            "type": "button",
+            "text": { "type": "plain_text", "text": clip(&d.label, 75) },
+            "url": d.value,
+        }))
+        .collect();
+    if !buttons.is_empty() {
+        blocks.push(json!({ "type": "actions", "elements": buttons }));
+    }
+    blocks
+}
+
+#[async_trait]
+impl Sink for SlackSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from slack..");
+        // "text" is the fallback shown in notifications
+        let payload = json!({ "text": escape(ev.headline()), "blocks": blocks(ev) });
+        self.hook.post(&self.client, payload).await?;
+        debug!("Sent by slack to {}", self.name);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("slack:{}", self.name)
+    }
+
+    /// Template variables cannot add mentions (`<!channel>`) or links.
+    fn escape(&self, value: &str) -> String {
+        escape(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::template::Template;
+    use crate::notifiers::testing::http_server;
+    use crate::notifiers::{Detail, Notifier, SinkContext};
+
+    fn matched(line: &str) -> NotifyEvent {
+        NotifyEvent { title: "matched".to_string(), payload: json!({ "line": line }), ..Default::default() }
+    }
+
+    /// An alert with `n` short details, a log excerpt and a link.
+    fn detailed(n: usize) -> NotifyEvent {
+        let mut details: Vec<Detail> = (0..n).map(|i| Detail::text(&format!("k{i}"), format!("<v{i}>"))).collect();
+        details.push(Detail::code("Lines", "a ``` b"));
+        details.push(Detail::link("Report", "https://example.org/r"));
+        NotifyEvent { title: "t".repeat(200), job: Some("auth".to_string()), details, ..Default::default() }
+    }
+
+    #[test]
+    fn blocks_of_a_detailed_alert() {
+        let blocks = blocks(&detailed(12));
+        let kinds: Vec<&str> = blocks.iter().map(|b| b["type"].as_str().unwrap()).collect();
+        assert_eq!(kinds, ["header", "section", "section", "section", "actions"]);
+        assert_eq!(blocks[0]["text"]["text"].as_str().unwrap().chars().count(), 150);
+        // Job + 12 details: a section holds 10 fields
+        assert_eq!(blocks[1]["fields"].as_array().unwrap().len(), 10);
+        assert_eq!(blocks[2]["fields"].as_array().unwrap().len(), 3);
+        assert_eq!(blocks[1]["fields"][1]["text"], "*k0*\n&lt;v0&gt;");
+        // The content cannot close the code block
+        let code = blocks[3]["text"]["text"].as_str().unwrap();
+        assert_eq!(code, "*Lines*\n```\na `\u{200b}`` b\n```");
+        assert_eq!(blocks[4]["elements"][0]["url"], "https://example.org/r");
+    }
+
+    #[test]
+    fn blocks_of_a_plain_message() {
+        let ev = NotifyEvent { msg: "x".repeat(5000), ..Default::default() };
+        let blocks = blocks(&ev);
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[1]["text"]["text"].as_str().unwrap().chars().count() <= 2900);
+    }
+
+    #[tokio::test]
+    async fn template_variables_cannot_mention_or_link() {
+        let (url, requests) = http_server(vec![]);
+        let ctx = SinkContext {
+            slack: [("ops".to_string(), IncomingWebhook { url, channel: None, username: None })].into(),
+            template: Some(Template::parse("*{{line}}*").unwrap()),
+            ..Default::default()
+        };
+        let notifier = Notifier::new(vec!["slack:ops".to_string()], &ctx).unwrap();
+        notifier.notify_event(matched("<!channel> see <https://evil|click here> & go"));
+        let drained = notifier.drained();
+        drop(notifier);
+        drained.await;
+
+        let body = requests.lock().unwrap()[0].json();
+        assert_eq!(body["blocks"][1]["text"]["text"], "*&lt;!channel&gt; see &lt;https://evil|click here&gt; &amp; go*");
+    }
+}
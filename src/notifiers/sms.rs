@@ -0,0 +1,365 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use log::{info,debug,warn};
+use serde::Deserialize;
+
+use crate::notifiers::{http_client, Delivered, Format, NotifyEvent, Sink};
+
+/// HTTP gateway used to send the SMS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmsProvider {
+    /// Twilio Messages API (`account` = account SID, `token` = auth token)
+    Twilio,
+    /// Vonage (Nexmo) SMS API (`account` = API key, `token` = API secret)
+    Vonage,
+    /// Any HTTP API: POST `params` to `url`
+    Generic,
+}
+
+/// Encoding of the generic provider request body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyFormat {
+    #[default]
+    Json,
+    Form,
+}
+
+/// SMS gateway used by the `sms:` sinks (top-level `sms` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmsSettings {
+    pub provider: SmsProvider,
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Sender number or name
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Generic provider: endpoint
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub format: BodyFormat,
+    /// Generic provider: extra headers (e.g. `Authorization`)
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Generic provider: body parameters, `{{to}}`, `{{from}}` and `{{text}}` are replaced
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// Long alerts are split in up to this many SMS, the last one truncated
+    #[serde(default = "default_max_parts")]
+    pub max_parts: usize,
+    /// At most `rate_limit` SMS per recipient every `rate_period` seconds, the rest is dropped
+    #[serde(default = "default_rate_limit")]
+    pub rate_limit: usize,
+    #[serde(default = "default_rate_period")]
+    pub rate_period: u64,
+}
+
+fn default_max_parts() -> usize { 1 }
+fn default_rate_limit() -> usize { 10 }
+fn default_rate_period() -> u64 { 3600 }
+
+/// Recent sends per recipient, shared by all jobs.
+fn sent_log() -> &'static Mutex<HashMap<String, VecDeque<Instant>>> {
+    static SENT: OnceLock<Mutex<HashMap<String, VecDeque<Instant>>>> = OnceLock::new();
+    SENT.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[derive(Debug)]
+pub struct SmsSink {
+    client: reqwest::Client,
+    settings: SmsSettings,
+    to: String,
+    /// Parts sent, so a retry does not send (and bill) the first parts twice
+    delivered: Delivered<usize>,
+}
+
+impl SmsSink {
+    pub fn new(settings: &SmsSettings, to: &str) -> Result<Self> {
+        if !is_e164(to) {
+            anyhow::bail!("Invalid SMS number '{to}', expected E.164 (e.g. +33612345678)");
+        }
+        let missing = match settings.provider {
+            SmsProvider::Twilio | SmsProvider::Vonage => {
+                [("account", &settings.account), ("token", &settings.token), ("from", &settings.from)]
+                    .into_iter()
+                    .find(|(_, v)| v.is_none())
+                    .map(|(k, _)| k)
+            }
+            SmsProvider::Generic if settings.url.is_none() => Some("url"),
+            SmsProvider::Generic if settings.params.is_empty() => Some("params"),
+            SmsProvider::Generic => None,
+        };
+        if let Some(key) = missing {
+            anyhow::bail!("sms: '{key}' is required by the {:?} provider", settings.provider);
+        }
+        if settings.max_parts == 0 || settings.rate_limit == 0 {
+            anyhow::bail!("sms: 'max_parts' and 'rate_limit' must be at least 1");
+        }
+        let client = http_client()?;
+        Ok(Self { client, settings: settings.clone(), to: to.to_string(), delivered: Delivered::default() })
+    }
+
+    /// Recent sends to the recipient, older ones dropped.
+    fn recent(&self) -> usize {
+        let period = Duration::from_secs(self.settings.rate_period);
+        let mut sent = sent_log().lock().unwrap_or_else(|e| e.into_inner());
+        let times = sent.entry(self.to.clone()).or_default();
+        while times.front().is_some_and(|t| t.elapsed() >= period) {
+            times.pop_front();
+        }
+        times.len()
+    }
+
+    /// Count a delivered SMS against the recipient's budget.
+    fn record(&self) {
+        let mut sent = sent_log().lock().unwrap_or_else(|e| e.into_inner());
+        sent.entry(self.to.clone()).or_default().push_back(Instant::now());
+    }
+
+    async fn send_one(&self, text: &str) -> Result<()> {
+        let s = &self.settings;
+        let from = s.from.clone().unwrap_or_default();
+        let res = match s.provider {
+            SmsProvider::Twilio => {
+                let account = s.account.as_deref().unwrap_or_default();
+                self.client
+                    .post(format!("https://api.twilio.com/2010-04-01/Accounts/{account}/Messages.json"))
+                    .basic_auth(account, s.token.as_deref())
+                    .form(&[("To", self.to.as_str()), ("From", from.as_str()), ("Body", text)])
+                    .send()
+                    .await?
+            }
+            SmsProvider::Vonage => {
+                let res = self.client
+                    .post("https://rest.nexmo.com/sms/json")
+                    .form(&[
+                        ("api_key", s.account.as_deref().unwrap_or_default()),
+                        ("api_secret", s.token.as_deref().unwrap_or_default()),
+                        ("to", self.to.trim_start_matches('+')),
+                        ("from", from.as_str()),
+                        ("text", text),
+                        ("type", if is_gsm(text) { "text" } else { "unicode" }),
+                    ])
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                // Errors come back as HTTP 200 with a non-zero status per message
+                let body: serde_json::Value = res.json().await?;
+                if let Some(m) = body["messages"].as_array().and_then(|m| m.first())
+                    && m["status"].as_str() != Some("0") {
+                    anyhow::bail!("Vonage error: {}", m["error-text"].as_str().unwrap_or("unknown"));
+                }
+                return Ok(());
+            }
+            SmsProvider::Generic => {
+                let params: BTreeMap<&str, String> = s.params
+                    .iter()
+                    .map(|(k, v)| {
+                        let v = v.replace("{{to}}", &self.to).replace("{{from}}", &from).replace("{{text}}", text);
+                        (k.as_str(), v)
+                    })
+                    .collect();
+                let mut req = self.client.post(s.url.as_deref().unwrap_or_default());
+                for (k, v) in &s.headers {
+                    req = req.header(k, v);
+                }
+                req = match s.format {
+                    BodyFormat::Json => req.json(&params),
+                    BodyFormat::Form => req.form(&params),
+                };
+                req.send().await?
+            }
+        };
+        res.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for SmsSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from sms..");
+        // Blank lines cost characters
+        let text = ev.render(Format::Plain);
+        let text: Vec<&str> = text.lines().map(str::trim_end).filter(|l| !l.is_empty()).collect();
+        let start = self.delivered.get(ev.id);
+        let parts = split_sms(&text.join("\n"), self.settings.max_parts);
+        for (i, part) in parts.iter().enumerate().skip(start) {
+            if self.recent() >= self.settings.rate_limit {
+                warn!("SMS rate limit reached for {}, alert dropped", self.to);
+                return Ok(());
+            }
+            self.send_one(part).await?;
+            self.record();
+            self.delivered.set(ev.id, i + 1);
+        }
+        debug!("Sent by sms to {}", self.to);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("sms:{}", self.to)
+    }
+}
+
+/// "+" followed by 2 to 15 digits, no leading zero.
+fn is_e164(number: &str) -> bool {
+    let Some(digits) = number.strip_prefix('+') else { return false };
+    (2..=15).contains(&digits.len())
+        && digits.bytes().all(|b| b.is_ascii_digit())
+        && !digits.starts_with('0')
+}
+
+/// Characters of the GSM 7-bit extension table, sent as an escape plus the character.
+const GSM_EXTENSION: &str = "[]{}\\^~|";
+
+/// True if the text fits the GSM 7-bit alphabet (approximated as printable ASCII).
+fn is_gsm(text: &str) -> bool {
+    text.chars().all(|c| c == '\n' || (' '..='~').contains(&c))
+}
+
+/// Size of a character in an SMS: septets in GSM 7-bit (2 for the extension
+/// table), UTF-16 code units in UCS-2.
+fn sms_width(c: char, gsm: bool) -> usize {
+    match gsm {
+        true if GSM_EXTENSION.contains(c) => 2,
+        true => 1,
+        false => c.len_utf16(),
+    }
+}
+
+/// Longest head of `text` fitting in `max`, and the rest.
+fn take_width(text: &str, max: usize, gsm: bool) -> (&str, &str) {
+    let mut width = 0;
+    for (i, c) in text.char_indices() {
+        width += sms_width(c, gsm);
+        if width > max {
+            return text.split_at(i);
+        }
+    }
+    (text, "")
+}
+
+/// Split a text in SMS-sized parts ("(1/3) " markers), at most `max_parts`;
+/// what does not fit is cut and the last part ends with "...".
+fn split_sms(text: &str, max_parts: usize) -> Vec<String> {
+    let gsm = is_gsm(text);
+    let (single, multi) = if gsm { (160, 153) } else { (70, 67) };
+    if text.chars().map(|c| sms_width(c, gsm)).sum::<usize>() <= single {
+        return vec![text.to_string()];
+    }
+    if max_parts == 1 {
+        return vec![format!("{}...", take_width(text, single - 3, gsm).0)];
+    }
+
+    // "(i/n) " markers, sized for the largest part count
+    let size = multi - (2 * max_parts.to_string().len() + 4);
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() && parts.len() < max_parts {
+        let (part, tail) = take_width(rest, size, gsm);
+        parts.push(part.to_string());
+        rest = tail;
+    }
+    if !rest.is_empty()
+        && let Some(last) = parts.last_mut() {
+        *last = format!("{}...", take_width(last, size - 3, gsm).0);
+    }
+    let total = parts.len();
+    parts.into_iter().enumerate().map(|(i, p)| format!("({}/{total}) {p}", i + 1)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn width(part: &str) -> usize {
+        let gsm = is_gsm(part);
+        part.chars().map(|c| sms_width(c, gsm)).sum()
+    }
+
+    #[test]
+    fn short_text_is_one_sms() {
+        assert_eq!(split_sms("hello", 3), vec!["hello"]);
+    }
+
+    #[test]
+    fn extension_characters_count_twice() {
+        // 100 braces are 200 septets: over one SMS although only 100 characters
+        let text = "{}".repeat(50);
+        let parts = split_sms(&text, 3);
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|p| width(p) <= 153), "{parts:?}");
+        assert_eq!(parts.concat().matches(['{', '}']).count(), 100);
+    }
+
+    #[test]
+    fn truncated_single_sms_fits() {
+        let parts = split_sms(&"|".repeat(200), 1);
+        assert_eq!(parts.len(), 1);
+        assert!(width(&parts[0]) <= 160);
+        assert!(parts[0].ends_with("..."));
+    }
+
+    #[test]
+    fn unicode_parts_fit_ucs2() {
+        let parts = split_sms(&"é".repeat(150), 5);
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|p| p.encode_utf16().count() <= 67), "{parts:?}");
+    }
+
+    #[test]
+    fn overflow_is_cut_with_ellipsis() {
+        let parts = split_sms(&"a".repeat(1000), 2);
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].starts_with("(1/2) "));
+        assert!(parts[1].starts_with("(2/2) ") && parts[1].ends_with("..."));
+        assert!(parts.iter().all(|p| width(p) <= 153));
+    }
+
+    #[tokio::test]
+    async fn retry_resumes_after_the_parts_sent() {
+        // Part 2 fails once: the retry must only send parts 2 and 3
+        let (url, requests) = crate::notifiers::testing::http_server(vec![200, 500]);
+        let settings = SmsSettings {
+            provider: SmsProvider::Generic,
+            account: None,
+            token: None,
+            from: None,
+            url: Some(url),
+            format: BodyFormat::Json,
+            headers: BTreeMap::new(),
+            params: [("to".to_string(), "{{to}}".to_string()), ("text".to_string(), "{{text}}".to_string())].into(),
+            max_parts: 3,
+            rate_limit: 100,
+            rate_period: 60,
+        };
+        let sink = SmsSink::new(&settings, "+33600000001").unwrap();
+        let ev = NotifyEvent { id: 7, msg: "x".repeat(400), ..Default::default() };
+        assert!(sink.send(&ev).await.is_err());
+        sink.send(&ev).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().all(|r| r.method == "POST" && r.path == "/"));
+        assert_eq!(requests[0].header("content-type"), Some("application/json"));
+        let texts: Vec<String> = requests.iter()
+            .map(|r| r.json()["text"].as_str().unwrap_or_default()[..6].to_string())
+            .collect();
+        assert_eq!(texts, ["(1/3) ", "(2/3) ", "(2/3) ", "(3/3) "]);
+    }
+
+    #[test]
+    fn e164_numbers() {
+        assert!(is_e164("+33612345678"));
+        assert!(!is_e164("0612345678"));
+        assert!(!is_e164("+0612345678"));
+        assert!(!is_e164("+33 6 12"));
+    }
+}
@@ -1,12 +1,33 @@
 use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
 use teloxide::{prelude::*, types::ParseMode}; // brings Requester
 use teloxide::types::ChatId;
-use log::{info,debug,error};
+use teloxide::{ApiError, RequestError};
+use log::{info,debug,warn};
+
+use crate::notifiers::{Delivered, Format, NotifyEvent, Sink};
+
+/// Telegram limit (UTF-16 code units of one message, once the HTML is parsed).
+const MESSAGE_MAX: usize = 4096;
+/// Room kept for the "(1/3)" marker line of split messages.
+const MARKER_MAX: usize = 16;
+
+/// What a previous attempt already posted of an alert.
+#[derive(Clone, Copy, Debug, Default)]
+struct Progress {
+    /// HTML parts posted
+    html: usize,
+    /// Plain text parts posted, once Telegram refused the HTML
+    plain: Option<usize>,
+}
 
 #[derive(Clone)]
 pub struct TelegramSink {
     bot: Bot,
     chat_id: ChatId,
+    /// So a retry neither posts the first parts twice nor goes back to HTML
+    delivered: Arc<Delivered<Progress>>,
 }
 
 impl std::fmt::Debug for TelegramSink {
@@ -17,26 +38,358 @@ impl std::fmt::Debug for TelegramSink {
 
 impl TelegramSink {
     pub fn new(token: String, chat_id: i64) -> Self {
-        let bot = Bot::new(token);
-        // Optional: validate token once; ignore errors to avoid failing the whole app.
-        let bot_clone = bot.clone();
-        tokio::spawn(async move {
-            match bot_clone.get_me().await {
-                Ok(me) => debug!(
-                    "Telegram running as @{} (id={}) for one job!",
-                    me.user.username.as_deref().unwrap_or("unknown"),
-                    me.user.id.0
-                ),
-                Err(e) => error!("Telegram getMe error: {e}"),
+        Self { bot: Bot::new(token), chat_id: ChatId(chat_id), delivered: Arc::default() }
+    }
+
+    /// Send the HTML parts of an alert not sent yet by a previous attempt.
+    async fn deliver_html(&self, id: u64, parts: &[String]) -> Result<(), RequestError> {
+        let mut d = self.delivered.get(id);
+        for part in &parts[d.html.min(parts.len())..] {
+            self.bot.send_message(self.chat_id, part).parse_mode(ParseMode::Html).await?;
+            d.html += 1;
+            self.delivered.set(id, d);
+        }
+        Ok(())
+    }
+
+    /// Send the plain text parts of an alert not sent yet by a previous attempt.
+    async fn deliver_plain(&self, id: u64, parts: &[String]) -> Result<(), RequestError> {
+        let mut d = self.delivered.get(id);
+        let mut sent = d.plain.unwrap_or(0);
+        // Stay in plain text for the retries, even when nothing went through
+        d.plain = Some(sent);
+        self.delivered.set(id, d);
+        for part in &parts[sent.min(parts.len())..] {
+            self.bot.send_message(self.chat_id, part).await?;
+            sent += 1;
+            d.plain = Some(sent);
+            self.delivered.set(id, d);
+        }
+        Ok(())
+    }
+}
+
+/// Smallest pieces a message can be cut between: tags, entities and characters
+/// (only characters for plain text).
+fn tokens(s: &str, html: bool) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let end = match c {
+            '<' if html => rest.find('>').map_or(1, |i| i + 1),
+            '&' if html => rest.char_indices().take(10).find(|&(_, c)| c == ';').map_or(1, |(i, _)| i + 1),
+            c => c.len_utf8(),
+        };
+        out.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// Track the tags still open after `tok`, as (name, opening tag).
+fn apply(open: &mut Vec<(String, String)>, tok: &str) {
+    if let Some(name) = tok.strip_prefix("</") {
+        let name = name.trim_end_matches('>').trim();
+        if let Some(i) = open.iter().rposition(|(n, _)| n == name) {
+            open.remove(i);
+        }
+    } else if tok.len() > 1 && tok.starts_with('<') && tok.ends_with('>') {
+        let name = tok[1..tok.len() - 1].split_whitespace().next().unwrap_or_default();
+        open.push((name.to_string(), tok.to_string()));
+    }
+}
+
+/// Length counted by Telegram: tags are dropped, entities are one character.
+fn width(tok: &str, html: bool) -> usize {
+    match tok.chars().next() {
+        Some('<') if html && tok.len() > 1 => 0,
+        Some('&') if html && tok.len() > 1 => 1,
+        _ => tok.encode_utf16().count(),
+    }
+}
+
+/// Parts being built by `split`.
+#[derive(Default)]
+struct Parts {
+    done: Vec<String>,
+    cur: String,
+    width: usize,
+    /// Tags open at the end of `cur`, as (name, opening tag)
+    open: Vec<(String, String)>,
+}
+
+impl Parts {
+    fn push(&mut self, toks: &[&str], width: usize) {
+        for tok in toks {
+            apply(&mut self.open, tok);
+            self.cur.push_str(tok);
+        }
+        self.width += width;
+    }
+
+    /// Close the current part (and its open tags), the next one reopens them.
+    fn cut(&mut self) {
+        if self.width > 0 {
+            self.done.push(format!("{}{}", self.cur.trim_end_matches('\n'), closing(&self.open)));
+        }
+        self.cur = self.open.iter().map(|(_, tag)| tag.as_str()).collect();
+        self.width = 0;
+    }
+}
+
+fn closing(open: &[(String, String)]) -> String {
+    open.iter().rev().map(|(n, _)| format!("</{n}>")).collect()
+}
+
+/// Split `text` in messages of at most `max` characters, on line boundaries (inside
+/// a line only when it is too long by itself). Tags open at a cut are closed, then
+/// reopened in the next part.
+fn split(text: &str, html: bool, max: usize) -> Vec<String> {
+    let mut parts = Parts::default();
+    for line in text.split_inclusive('\n') {
+        let toks = tokens(line, html);
+        let line_width = toks.iter().map(|t| width(t, html)).sum::<usize>();
+        if parts.width + line_width > max {
+            parts.cut();
+        }
+        if parts.width + line_width <= max {
+            parts.push(&toks, line_width);
+            continue;
+        }
+        for tok in toks {
+            let w = width(tok, html);
+            if parts.width + w > max {
+                parts.cut();
             }
-        });
-        Self { bot, chat_id: ChatId(chat_id) }
+            parts.push(&[tok], w);
+        }
+    }
+    parts.cut();
+
+    let n = parts.done.len();
+    if n < 2 {
+        return parts.done;
+    }
+    parts.done.into_iter().enumerate().map(|(i, p)| format!("({}/{n})\n{p}", i + 1)).collect()
+}
+
+/// Tags of the Telegram HTML style (anything else in `<...>` is text).
+const TAGS: &[&str] = &[
+    "b", "strong", "i", "em", "u", "ins", "s", "strike", "del", "a", "code", "pre",
+    "span", "tg-spoiler", "tg-emoji", "blockquote",
+];
+
+/// Text of an HTML part: known tags dropped, entities decoded.
+fn strip_html(part: &str) -> String {
+    let mut out = String::with_capacity(part.len());
+    for tok in tokens(part, true) {
+        if let Some(tag) = tok.strip_prefix('<').filter(|_| tok.len() > 1 && tok.ends_with('>')) {
+            let name = tag.trim_start_matches('/').trim_end_matches('>').split_whitespace().next().unwrap_or_default();
+            if TAGS.contains(&name) {
+                continue;
+            }
+        } else if let Some(entity) = tok.strip_prefix('&').and_then(|e| e.strip_suffix(';')) {
+            let c = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                e => e.strip_prefix("#x").or(e.strip_prefix("#X"))
+                    .map_or_else(|| e.strip_prefix('#').and_then(|n| n.parse().ok()), |n| u32::from_str_radix(n, 16).ok())
+                    .and_then(char::from_u32),
+            };
+            if let Some(c) = c {
+                out.push(c);
+                continue;
+            }
+        }
+        out.push_str(tok);
+    }
+    out
+}
+
+/// Telegram refused the markup (unbalanced or unsupported tags, bad entities).
+fn cant_parse(e: &RequestError) -> bool {
+    match e {
+        RequestError::Api(ApiError::CantParseEntities) => true,
+        RequestError::Api(ApiError::Unknown(msg)) => msg.contains("can't parse entities"),
+        _ => false,
     }
+}
 
-    pub async fn send(&self, html: &str) -> Result<()> {
+#[async_trait]
+impl Sink for TelegramSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
         info!("Sending notification from telegram..");
-        self.bot.send_message(self.chat_id, html).parse_mode(ParseMode::Html).await?;
+        let html = split(&ev.render(Format::TelegramHtml), true, MESSAGE_MAX - MARKER_MAX);
+        if self.delivered.get(ev.id).plain.is_none() {
+            match self.deliver_html(ev.id, &html).await {
+                Err(e) if cant_parse(&e) => {
+                    warn!("Telegram rejected the HTML of the alert ({e}), sending it as plain text");
+                }
+                res => {
+                    res?;
+                    debug!("Sent by telegram to UserId({})",&self.chat_id);
+                    return Ok(());
+                }
+            }
+        }
+        // Parts already posted in HTML are not repeated: the rest follows as text
+        let plain = match self.delivered.get(ev.id).html {
+            0 => split(&ev.render(Format::Plain), false, MESSAGE_MAX - MARKER_MAX),
+            n => html[n.min(html.len())..].iter().map(|p| strip_html(p)).collect(),
+        };
+        self.deliver_plain(ev.id, &plain).await?;
         debug!("Sent by telegram to UserId({})",&self.chat_id);
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn name(&self) -> String {
+        format!("tg:{}", self.chat_id.0)
+    }
+
+    /// Validate the token once (getMe); errors are only logged by the notifier.
+    async fn health_check(&self) -> Result<()> {
+        let me = self.bot.get_me().await?;
+        debug!(
+            "Telegram running as @{} (id={}) for one job!",
+            me.user.username.as_deref().unwrap_or("unknown"),
+            me.user.id.0
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::testing::{http_server_with, Request};
+
+    const SENT: &str = r#"{"ok":true,"result":{"message_id":1,"date":0,"chat":{"id":1,"type":"private"},"text":"x"}}"#;
+
+    fn refused() -> (u16, String) {
+        (400, r#"{"ok":false,"error_code":400,"description":"Bad Request: can't parse entities: unexpected end tag"}"#.to_string())
+    }
+
+    fn failed() -> (u16, String) {
+        (400, r#"{"ok":false,"error_code":400,"description":"Bad Request: chat not found"}"#.to_string())
+    }
+
+    /// A sink posting to a local stub, and an alert of 3 messages.
+    fn setup(replies: Vec<(u16, String)>) -> (TelegramSink, NotifyEvent, Arc<std::sync::Mutex<Vec<Request>>>) {
+        let (url, requests) = http_server_with(replies, SENT);
+        let mut sink = TelegramSink::new("123:abc".to_string(), 1);
+        sink.bot = sink.bot.set_api_url(reqwest::Url::parse(&url).unwrap());
+        let text = (0..700).map(|i| format!("<b>line {i:04}</b> a &amp; b\n")).collect::<String>();
+        let ev = NotifyEvent { id: 9, text: Some(text), ..Default::default() };
+        (sink, ev, requests)
+    }
+
+    /// (HTML?, marker) of each message posted.
+    fn posted(requests: &std::sync::Mutex<Vec<Request>>) -> Vec<(bool, String)> {
+        requests.lock().unwrap().iter().map(|r| {
+            let body = r.json();
+            let text = body["text"].as_str().unwrap();
+            (body.get("parse_mode").is_some(), text.lines().next().unwrap().to_string())
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn fallback_continues_after_the_html_parts_sent() {
+        let (sink, ev, requests) = setup(vec![(200, SENT.to_string()), refused()]);
+        sink.send(&ev).await.unwrap();
+
+        let posted = posted(&requests);
+        assert_eq!(posted, [
+            (true, "(1/3)".to_string()),
+            (true, "(2/3)".to_string()),
+            (false, "(2/3)".to_string()),
+            (false, "(3/3)".to_string()),
+        ]);
+        let last = requests.lock().unwrap()[3].json()["text"].as_str().unwrap().to_string();
+        assert!(last.contains("line 0699 a & b") && !last.contains("<b>"), "{last}");
+    }
+
+    #[tokio::test]
+    async fn retry_after_fallback_stays_plain() {
+        let (sink, ev, requests) = setup(vec![(200, SENT.to_string()), refused(), failed()]);
+        assert!(sink.send(&ev).await.is_err());
+        sink.send(&ev).await.unwrap();
+
+        assert_eq!(posted(&requests), [
+            (true, "(1/3)".to_string()),
+            (true, "(2/3)".to_string()),
+            (false, "(2/3)".to_string()),
+            (false, "(2/3)".to_string()),
+            (false, "(3/3)".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn html_refused_at_once_is_sent_as_plain_render() {
+        let (sink, mut ev, requests) = setup(vec![refused()]);
+        ev.text = Some("<b>disk</b> full".to_string());
+        sink.send(&ev).await.unwrap();
+
+        let bodies: Vec<_> = requests.lock().unwrap().iter().map(Request::json).collect();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[1]["text"], "<b>disk</b> full");
+        assert!(bodies[1].get("parse_mode").is_none());
+    }
+
+    /// Length of a part as counted by Telegram.
+    fn telegram_width(part: &str, html: bool) -> usize {
+        tokens(part, html).iter().map(|t| width(t, html)).sum()
+    }
+
+    #[test]
+    fn split_at_exactly_the_limit() {
+        let text = "x".repeat(MESSAGE_MAX);
+        assert_eq!(split(&text, false, MESSAGE_MAX), [text.as_str()]);
+        let parts = split(&format!("{text}y"), false, MESSAGE_MAX);
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].starts_with("(1/2)\n") && parts[1] == "(2/2)\ny");
+        // Entities count as one character, tags as none
+        let html = format!("<b>{}</b>", "&amp;".repeat(MESSAGE_MAX));
+        assert_eq!(split(&html, true, MESSAGE_MAX), [html.as_str()]);
+        // Characters outside the BMP count twice
+        assert_eq!(split(&"😀".repeat(MESSAGE_MAX / 2 + 1), false, MESSAGE_MAX).len(), 2);
+    }
+
+    #[test]
+    fn parts_with_marker_fit_in_a_message() {
+        let text = (0..2000).map(|i| format!("line {i}\n")).collect::<String>();
+        let parts = split(&text, false, MESSAGE_MAX - MARKER_MAX);
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(telegram_width(part, false) <= MESSAGE_MAX);
+            // Cut between lines
+            assert!(part.ends_with(|c: char| c.is_ascii_digit()), "{part:?}");
+        }
+        let joined: String = parts.iter().map(|p| p.split_once('\n').unwrap().1).collect::<Vec<_>>().join("\n");
+        assert_eq!(joined, text.trim_end());
+    }
+
+    #[test]
+    fn split_closes_and_reopens_tags() {
+        let text = format!("<pre>{}</pre>", "a".repeat(30));
+        let parts = split(&text, true, 20);
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].ends_with("</pre>"));
+        assert!(parts[1].starts_with("(2/2)\n<pre>") && parts[1].ends_with("</pre>"));
+        assert!(parts.iter().all(|p| telegram_width(p.split_once('\n').unwrap().1, true) <= 20));
+        // An entity is never cut
+        let parts = split(&"&lt;".repeat(25), true, 20);
+        assert!(parts.iter().all(|p| p.ends_with("&lt;")));
+    }
+
+    #[test]
+    fn empty_text_has_no_part() {
+        assert!(split("", true, MESSAGE_MAX).is_empty());
+    }
+
+    #[test]
+    fn strip_html_keeps_unknown_tags_as_text() {
+        assert_eq!(strip_html("<b>a &lt; b</b> &amp; <pre>x</pre> &#65;&#x42; <c>"), "a < b & x AB <c>");
+    }
+}
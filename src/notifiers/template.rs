@@ -0,0 +1,379 @@
+use anyhow::{Result, Context};
+use std::collections::BTreeMap;
+use serde_json::Value;
+
+use crate::notifiers::render::html_escape;
+use crate::notifiers::{Format, NotifyEvent};
+
+/// Transformation applied to a variable: `{{ line | truncate(80) | escape }}`.
+#[derive(Clone, Debug, PartialEq)]
+enum Filter {
+    Upper,
+    Lower,
+    Trim,
+    /// HTML escaping (done anyway for HTML destinations)
+    Escape,
+    /// No HTML escaping: the variable holds markup
+    Raw,
+    /// At most N characters, the last one "…" when cut
+    Truncate(usize),
+    /// Used when the variable is missing or empty
+    Default(String),
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Text(String),
+    Var { name: String, filters: Vec<Filter> },
+    If { name: String, then: Vec<Node>, otherwise: Vec<Node> },
+}
+
+/// A message template (`template` YAML keys, webhook bodies, email subjects).
+///
+/// `{{ name }}` is replaced by a variable of the alert, optionally piped through
+/// filters (`upper`, `lower`, `trim`, `escape`, `raw`, `truncate(N)`, `default("text")`).
+/// `{{#if name}} .. {{else}} .. {{/if}}` keeps a part when the variable is not empty.
+/// Variables are escaped for the markup of the destination: HTML for Telegram,
+/// Matrix and email (unless piped through `raw`), mrkdwn for Slack, Markdown for
+/// Mattermost and Discord.
+#[derive(Clone, Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// `name | filter(..) | ..` cut on the `|` outside of quoted arguments.
+fn split_pipes(tag: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted) = (0, false);
+    for (i, c) in tag.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '|' if !quoted => {
+                parts.push(tag[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(tag[start..].trim());
+    parts
+}
+
+fn parse_filter(s: &str) -> Result<Filter> {
+    let (name, arg) = match s.split_once('(') {
+        Some((name, rest)) => {
+            let Some(arg) = rest.strip_suffix(')') else {
+                anyhow::bail!("missing ')' in filter '{s}'");
+            };
+            (name.trim(), Some(arg.trim()))
+        }
+        None => (s, None),
+    };
+    Ok(match (name, arg) {
+        ("upper", None) => Filter::Upper,
+        ("lower", None) => Filter::Lower,
+        ("trim", None) => Filter::Trim,
+        ("escape", None) => Filter::Escape,
+        ("raw", None) => Filter::Raw,
+        ("truncate", Some(n)) => Filter::Truncate(n.parse().with_context(|| format!("truncate({n}): expected a number"))?),
+        ("default", Some(text)) => {
+            let text = match text.strip_prefix('"') {
+                Some(quoted) => quoted.strip_suffix('"').with_context(|| format!("unclosed '\"' in filter '{s}'"))?,
+                None => text,
+            };
+            Filter::Default(text.to_string())
+        }
+        ("truncate" | "default", None) => anyhow::bail!("filter '{name}' needs an argument"),
+        _ => anyhow::bail!("unknown filter '{s}'"),
+    })
+}
+
+impl Template {
+    /// Parse and check a template (unclosed tags, unknown filters, unbalanced `#if`).
+    pub fn parse(source: &str) -> Result<Self> {
+        // Open `#if` blocks: (condition, nodes before the block, "then" branch once in `else`)
+        let mut stack: Vec<(String, Vec<Node>, Option<Vec<Node>>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                nodes.push(Node::Text(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find("}}") else {
+                anyhow::bail!("unclosed '{{{{' at: {}", &rest[start..].chars().take(20).collect::<String>());
+            };
+            let tag = rest[start + 2..start + end].trim();
+            rest = &rest[start + end + 2..];
+
+            if let Some(name) = tag.strip_prefix("#if") {
+                let name = name.trim();
+                if !valid_name(name) {
+                    anyhow::bail!("invalid condition '{{{{{tag}}}}}'");
+                }
+                stack.push((name.to_string(), std::mem::take(&mut nodes), None));
+            } else if tag == "else" {
+                let Some((_, _, then)) = stack.last_mut() else {
+                    anyhow::bail!("'{{{{else}}}}' outside of '{{{{#if}}}}'");
+                };
+                if then.is_some() {
+                    anyhow::bail!("two '{{{{else}}}}' in one '{{{{#if}}}}'");
+                }
+                *then = Some(std::mem::take(&mut nodes));
+            } else if tag == "/if" {
+                let Some((name, outer, then)) = stack.pop() else {
+                    anyhow::bail!("'{{{{/if}}}}' without '{{{{#if}}}}'");
+                };
+                let inner = std::mem::replace(&mut nodes, outer);
+                let (then, otherwise) = match then {
+                    Some(then) => (then, inner),
+                    None => (inner, Vec::new()),
+                };
+                nodes.push(Node::If { name, then, otherwise });
+            } else {
+                let mut parts = split_pipes(tag).into_iter();
+                let name = parts.next().unwrap_or_default();
+                if !valid_name(name) {
+                    anyhow::bail!("invalid variable '{{{{{tag}}}}}'");
+                }
+                let filters = parts.map(parse_filter).collect::<Result<_>>()?;
+                nodes.push(Node::Var { name: name.to_string(), filters });
+            }
+        }
+        if !rest.is_empty() {
+            nodes.push(Node::Text(rest.to_string()));
+        }
+        if let Some((name, ..)) = stack.last() {
+            anyhow::bail!("'{{{{#if {name}}}}}' not closed by '{{{{/if}}}}'");
+        }
+        Ok(Self { nodes })
+    }
+
+    /// Fill the template; `escape` is applied to every variable after its filters.
+    pub fn render(&self, vars: &BTreeMap<String, String>, escape: impl Fn(&str) -> String) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, vars, &escape, false, &mut out);
+        out
+    }
+
+    /// Fill the template for an HTML destination: variables are HTML-escaped, except
+    /// the ones piped through `escape` (already done) or `raw`.
+    pub fn render_html(&self, vars: &BTreeMap<String, String>) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, vars, &str::to_string, true, &mut out);
+        out
+    }
+
+    /// Fill the template with the variables of an alert, see `variables`.
+    pub fn render_event(&self, ev: &NotifyEvent, escape: impl Fn(&str) -> String) -> String {
+        self.render(&variables(ev), escape)
+    }
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    vars: &BTreeMap<String, String>,
+    escape: &dyn Fn(&str) -> String,
+    html: bool,
+    out: &mut String,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var { name, filters } => {
+                let mut value = vars.get(name).cloned().unwrap_or_default();
+                for filter in filters {
+                    value = match filter {
+                        Filter::Upper => value.to_uppercase(),
+                        Filter::Lower => value.to_lowercase(),
+                        Filter::Trim => value.trim().to_string(),
+                        Filter::Escape => html_escape(&value),
+                        Filter::Raw => value,
+                        // No room for "…" and some text below 2 characters
+                        Filter::Truncate(n) if *n < 2 => value.chars().take(*n).collect(),
+                        Filter::Truncate(n) if value.chars().count() > *n => {
+                            value.chars().take(n - 1).chain(std::iter::once('…')).collect()
+                        }
+                        Filter::Truncate(_) => value,
+                        Filter::Default(text) if value.is_empty() => text.clone(),
+                        Filter::Default(_) => value,
+                    };
+                }
+                if html && !filters.iter().any(|f| matches!(f, Filter::Escape | Filter::Raw)) {
+                    value = html_escape(&value);
+                }
+                out.push_str(&escape(&value));
+            }
+            Node::If { name, then, otherwise } => {
+                let set = vars.get(name).is_some_and(|v| !v.is_empty());
+                render_nodes(if set { then } else { otherwise }, vars, escape, html, out);
+            }
+        }
+    }
+}
+
+/// Scalars as is, lists of scalars comma-separated.
+fn scalar(v: &Value) -> Option<String> {
+    match v {
+        Value::Null | Value::Object(_) => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Array(items) => Some(items.iter().filter_map(scalar).collect::<Vec<_>>().join(", ")),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Template variables of an alert: `job`, `title`, `source`, `severity`, `msg`, the data
+/// of the module (`path`/`file`, `line_no`, `line`, `rules`, ...; `hash`, `filename`,
+/// `url`, `ratio`, ... for VirusTotal; nested values as `captures.user`) and the
+/// captured groups by name.
+pub fn variables(ev: &NotifyEvent) -> BTreeMap<String, String> {
+    let mut vars: BTreeMap<String, String> = ev.fields.clone().into_iter().collect();
+    if let Value::Object(payload) = &ev.payload {
+        for (k, v) in payload {
+            match v {
+                Value::Object(inner) => {
+                    for (ik, iv) in inner {
+                        if let Some(iv) = scalar(iv) {
+                            vars.insert(format!("{k}.{ik}"), iv);
+                        }
+                    }
+                }
+                v => {
+                    if let Some(v) = scalar(v) {
+                        vars.insert(k.clone(), v);
+                    }
+                }
+            }
+        }
+        if let Some(file) = vars.get("file").cloned() {
+            vars.entry("path".to_string()).or_insert(file);
+        }
+    }
+    vars.insert("id".to_string(), ev.id.to_string());
+    vars.insert("job".to_string(), ev.job.clone().unwrap_or_default());
+    vars.insert("source".to_string(), ev.source.clone());
+    vars.insert("severity".to_string(), scalar(&serde_json::json!(ev.severity)).unwrap_or_default());
+    vars.insert("title".to_string(), ev.headline().to_string());
+    vars.insert("msg".to_string(), ev.render(Format::Plain));
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn plain(source: &str, pairs: &[(&str, &str)]) -> String {
+        Template::parse(source).unwrap().render(&vars(pairs), str::to_string)
+    }
+
+    #[test]
+    fn if_without_else() {
+        let t = "[{{job}}]{{#if user}} user {{user}}{{/if}}.";
+        assert_eq!(plain(t, &[("job", "ssh"), ("user", "root")]), "[ssh] user root.");
+        assert_eq!(plain(t, &[("job", "ssh")]), "[ssh].");
+        assert_eq!(plain(t, &[("job", "ssh"), ("user", "")]), "[ssh].");
+    }
+
+    #[test]
+    fn if_else_and_nesting() {
+        let t = "{{#if a}}A{{#if b}}B{{else}}-{{/if}}{{else}}none{{/if}}";
+        assert_eq!(plain(t, &[("a", "1"), ("b", "1")]), "AB");
+        assert_eq!(plain(t, &[("a", "1")]), "A-");
+        assert_eq!(plain(t, &[("b", "1")]), "none");
+    }
+
+    #[test]
+    fn variables_and_filters() {
+        assert_eq!(plain("{{ name }}/{{missing}}/{{ name | upper }}", &[("name", "Ab")]), "Ab//AB");
+        assert_eq!(plain("{{ x | trim | lower }}", &[("x", "  MiXed ")]), "mixed");
+        assert_eq!(plain("{{ x | default(none) }} {{ captures.user }}", &[("captures.user", "bob")]), "none bob");
+        assert_eq!(plain("no tags, { single } braces", &[]), "no tags, { single } braces");
+    }
+
+    #[test]
+    fn malformed_templates_are_refused() {
+        for t in [
+            "{{ job",
+            "{{#if a}}x",
+            "x{{/if}}",
+            "{{else}}",
+            "{{#if a}}1{{else}}2{{else}}3{{/if}}",
+            "{{#if }}x{{/if}}",
+            "{{ bad name }}",
+            "{{ x | shout }}",
+            "{{ x | truncate(many) }}",
+            "{{ x | truncate }}",
+            "{{ x | default(\"a\" }}",
+        ] {
+            assert!(Template::parse(t).is_err(), "{t}");
+        }
+    }
+
+    #[test]
+    fn event_variables() {
+        let ev = NotifyEvent {
+            id: 3,
+            job: Some("auth".to_string()),
+            source: "log-watcher".to_string(),
+            title: "matched".to_string(),
+            fields: [("user".to_string(), "root".to_string())].into(),
+            payload: serde_json::json!({ "file": "/var/log/auth.log", "rules": ["a", "b"], "captures": { "ip": "1.2.3.4" } }),
+            ..Default::default()
+        };
+        let v = variables(&ev);
+        assert_eq!(v["id"], "3");
+        assert_eq!(v["job"], "auth");
+        assert_eq!(v["path"], "/var/log/auth.log");
+        assert_eq!(v["rules"], "a, b");
+        assert_eq!(v["captures.ip"], "1.2.3.4");
+        assert_eq!(v["user"], "root");
+        assert_eq!(v["severity"], "normal");
+    }
+
+    #[test]
+    fn pipe_inside_quoted_argument() {
+        assert_eq!(plain(r#"{{ x | default("a|b") | upper }}"#, &[]), "A|B");
+        assert_eq!(plain(r#"{{ x | default("a|b") }}"#, &[("x", "set")]), "set");
+        assert!(Template::parse(r#"{{ x | default("a|b) }}"#).is_err());
+    }
+
+    #[test]
+    fn truncate_returns_at_most_n_characters() {
+        let line = [("line", "abcdef")];
+        assert_eq!(plain("{{ line | truncate(0) }}", &line), "");
+        assert_eq!(plain("{{ line | truncate(1) }}", &line), "a");
+        assert_eq!(plain("{{ line | truncate(2) }}", &line), "a…");
+        assert_eq!(plain("{{ line | truncate(5) }}", &line), "abcd…");
+        assert_eq!(plain("{{ line | truncate(6) }}", &line), "abcdef");
+        assert_eq!(plain("{{ line | truncate(3) }}", &[("line", "ééééé")]), "éé…");
+    }
+
+    #[test]
+    fn html_render_escapes_variables_but_not_the_template() {
+        let t = Template::parse("<b>{{job}}</b> <pre>{{line}}</pre> {{ link | raw }} {{ line | escape }}").unwrap();
+        let v = vars(&[("job", "a&b"), ("line", "<script>"), ("link", "<a href=\"x\">x</a>")]);
+        assert_eq!(
+            t.render_html(&v),
+            "<b>a&amp;b</b> <pre>&lt;script&gt;</pre> <a href=\"x\">x</a> &lt;script&gt;"
+        );
+        // Plain destinations get the values as they are
+        assert_eq!(t.render(&v, str::to_string), "<b>a&b</b> <pre><script></pre> <a href=\"x\">x</a> &lt;script&gt;");
+    }
+
+    #[test]
+    fn templated_event_renders_escaped_html() {
+        let t = Template::parse("{{line}}").unwrap();
+        let v = vars(&[("line", "1 < 2\nok")]);
+        let ev = NotifyEvent { text: Some(t.render(&v, str::to_string)), html_text: Some(t.render_html(&v)), ..Default::default() };
+        assert_eq!(ev.render(Format::Plain), "1 < 2\nok");
+        assert_eq!(ev.render(Format::TelegramHtml), "1 &lt; 2\nok");
+        assert_eq!(ev.render(Format::Html), "1 &lt; 2<br>\nok");
+    }
+}
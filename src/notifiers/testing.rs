@@ -0,0 +1,101 @@
+//! Local HTTP stand-in for the sinks calling web APIs, and a sink keeping the
+//! alerts it receives (tests only).
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::notifiers::{Notifier, NotifyEvent, Sink};
+
+/// Sink keeping every alert it receives.
+pub struct Capture(Arc<Mutex<Vec<NotifyEvent>>>);
+
+#[async_trait]
+impl Sink for Capture {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        self.0.lock().unwrap().push(ev.clone());
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        "capture".to_string()
+    }
+}
+
+/// A notifier delivering to a `Capture` sink, and the alerts received so far.
+pub fn capture() -> (Notifier, Arc<Mutex<Vec<NotifyEvent>>>) {
+    let alerts = Arc::new(Mutex::new(Vec::new()));
+    (Notifier::from_sinks(vec![Box::new(Capture(alerts.clone()))]), alerts)
+}
+
+/// One request received by the stub.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).expect("JSON body")
+    }
+}
+
+/// Serve HTTP on a free local port, answering the n-th request with `statuses[n]`
+/// (200 once the list is exhausted) and a JSON `{}` body. Returns the base URL and
+/// the requests received so far.
+pub fn http_server(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Request>>>) {
+    http_server_with(statuses.into_iter().map(|s| (s, "{}".to_string())).collect(), "{}")
+}
+
+/// Like `http_server`, with the body of each reply (`ok_body` once the list is exhausted).
+pub fn http_server_with(replies: Vec<(u16, String)>, ok_body: &str) -> (String, Arc<Mutex<Vec<Request>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    let ok_body = ok_body.to_string();
+    std::thread::spawn(move || {
+        for (n, stream) in listener.incoming().enumerate() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut parts = line.split_whitespace();
+            let (method, path) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or_default().to_string());
+            let mut headers = Vec::new();
+            loop {
+                let mut h = String::new();
+                reader.read_line(&mut h).unwrap();
+                let h = h.trim_end();
+                if h.is_empty() {
+                    break;
+                }
+                if let Some((k, v)) = h.split_once(':') {
+                    headers.push((k.trim().to_ascii_lowercase(), v.trim().to_string()));
+                }
+            }
+            let len = headers.iter().find(|(k, _)| k == "content-length").and_then(|(_, v)| v.parse().ok()).unwrap_or(0);
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            seen.lock().unwrap().push(Request { method, path, headers, body: String::from_utf8_lossy(&body).into_owned() });
+
+            let (status, body) = replies.get(n).map_or((200, ok_body.as_str()), |(s, b)| (*s, b.as_str()));
+            let reply = format!(
+                "HTTP/1.1 {status} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(reply.as_bytes());
+        }
+    });
+    (url, requests)
+}
@@ -0,0 +1,156 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use log::{info,debug};
+use serde::Deserialize;
+
+use crate::notifiers::template::Template;
+use crate::notifiers::{http_client, Format, NotifyEvent, Sink};
+
+/// A named webhook (entry of the top-level `webhooks` YAML key).
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSpec {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Signs the body with HMAC-SHA256, sent as `sha256=<hex>` in `signature_header`
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default = "default_signature_header")]
+    pub signature_header: String,
+    /// Body template, see `Template` (values are JSON-escaped).
+    /// Default: `{"title": ..., "job": ..., "message": ..., "fields": {...}}`
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+fn default_method() -> String { "POST".to_string() }
+fn default_signature_header() -> String { "X-Dende-Signature".to_string() }
+
+#[derive(Debug)]
+pub struct WebhookSink {
+    client: reqwest::Client,
+    name: String,
+    method: reqwest::Method,
+    spec: WebhookSpec,
+    body: Option<Template>,
+}
+
+impl WebhookSink {
+    pub fn new(name: &str, spec: &WebhookSpec) -> Result<Self> {
+        let method = reqwest::Method::from_bytes(spec.method.to_uppercase().as_bytes())
+            .with_context(|| format!("Webhook '{name}': invalid method '{}'", spec.method))?;
+        let body = spec.body.as_deref().map(Template::parse).transpose()
+            .with_context(|| format!("Webhook '{name}': invalid body template"))?;
+        let client = http_client()?;
+        Ok(Self { client, name: name.to_string(), method, spec: spec.clone(), body })
+    }
+
+    fn body(&self, ev: &NotifyEvent) -> String {
+        match &self.body {
+            Some(template) => template.render_event(ev, |v| {
+                // JSON string without its quotes, to be placed inside "..."
+                let quoted = serde_json::Value::from(v).to_string();
+                quoted[1..quoted.len() - 1].to_string()
+            }),
+            None => serde_json::json!({
+                "title": ev.headline(),
+                "job": ev.job,
+                "message": ev.render(Format::Plain),
+                "fields": ev.fields,
+            })
+            .to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for WebhookSink {
+    async fn send(&self, ev: &NotifyEvent) -> Result<()> {
+        info!("Sending notification from webhook..");
+        let body = self.body(ev);
+        let mut req = self.client.request(self.method.clone(), &self.spec.url);
+        if !self.spec.headers.keys().any(|k| k.eq_ignore_ascii_case("content-type")) {
+            req = req.header("Content-Type", "application/json");
+        }
+        for (k, v) in &self.spec.headers {
+            req = req.header(k, v);
+        }
+        if let Some(secret) = &self.spec.secret {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+            mac.update(body.as_bytes());
+            let signature = hex::encode(mac.finalize().into_bytes());
+            req = req.header(&self.spec.signature_header, format!("sha256={signature}"));
+        }
+        req.body(body).send().await?.error_for_status()?;
+        debug!("Sent by webhook to {}", self.name);
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("webhook:{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifiers::testing::http_server;
+
+    fn spec(url: String, body: Option<&str>, secret: Option<&str>) -> WebhookSpec {
+        WebhookSpec {
+            url,
+            method: "put".to_string(),
+            headers: BTreeMap::from([("X-Team".to_string(), "ops".to_string())]),
+            secret: secret.map(str::to_string),
+            signature_header: default_signature_header(),
+            body: body.map(str::to_string),
+        }
+    }
+
+    fn matched(line: &str) -> NotifyEvent {
+        NotifyEvent { title: "matched".to_string(), payload: serde_json::json!({ "line": line }), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn signature_is_the_hmac_of_the_body_received() {
+        let (url, requests) = http_server(vec![]);
+        let sink = WebhookSink::new("ops", &spec(format!("{url}/hook"), None, Some("s3cret"))).unwrap();
+        sink.send(&matched("ERR é")).await.unwrap();
+
+        let req = requests.lock().unwrap()[0].clone();
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("PUT", "/hook"));
+        assert_eq!(req.header("x-team"), Some("ops"));
+        assert_eq!(req.header("content-type"), Some("application/json"));
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+        mac.update(req.body.as_bytes());
+        let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        assert_eq!(req.header("x-dende-signature"), Some(expected.as_str()));
+        assert_eq!(req.json()["title"], "matched");
+    }
+
+    #[tokio::test]
+    async fn templated_body_stays_valid_json() {
+        let (url, requests) = http_server(vec![]);
+        let body = r#"{"text": "{{title}}: {{line}}", "user": "{{ user | default("none") }}"}"#;
+        let sink = WebhookSink::new("ops", &spec(url, Some(body), None)).unwrap();
+        let line = "say \"hi\"\n\tback\\slash </script>";
+        sink.send(&matched(line)).await.unwrap();
+
+        let json = requests.lock().unwrap()[0].json();
+        assert_eq!(json["text"], format!("matched: {line}"));
+        assert_eq!(json["user"], "none");
+        assert!(requests.lock().unwrap()[0].header("x-dende-signature").is_none());
+    }
+
+    #[tokio::test]
+    async fn error_status_is_an_error() {
+        let (url, _) = http_server(vec![500]);
+        let sink = WebhookSink::new("ops", &spec(url, None, None)).unwrap();
+        assert!(sink.send(&matched("x")).await.is_err());
+    }
+}